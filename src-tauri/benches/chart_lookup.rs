@@ -0,0 +1,230 @@
+//! Converts a generated beatoraja and LR2 library, comparing the joined chart query the
+//! converters use against looking each score's chart up separately.
+//!
+//! The chart tables have no index on the chart hash, so each separate lookup is a table scan.
+//!
+//! `cargo bench --bench chart_lookup`. Set `TIS_BENCH_SCORES` to change the library size
+//! (default 50000).
+
+use std::{
+	env, fs,
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
+use rusqlite::{Connection, OptionalExtension};
+
+use import_scripts_lib::backend::{
+	beatoraja::Beatoraja,
+	config::{default_ln_modes, BeatorajaConfig, LR2Config},
+	log::{Logger, SerializableLevel, StderrSink},
+	lr2::LR2,
+	source::{ConvertContext, ScoreSource},
+};
+
+/// Runs of each conversion to average over. The per-score lookups take minutes on a big
+/// library, so they only get one.
+const RUNS: u32 = 5;
+
+struct Fixture {
+	dir: PathBuf,
+}
+
+impl Fixture {
+	fn generate(scores: usize) -> rusqlite::Result<Self> {
+		let dir = env::temp_dir().join(format!("tis-bench-{}", std::process::id()));
+		fs::create_dir_all(&dir).expect("couldn't create fixture dir");
+
+		let score = Connection::open(dir.join("score.db"))?;
+		let songdata = Connection::open(dir.join("songdata.db"))?;
+		let lr2score = Connection::open(dir.join("lr2score.db"))?;
+		let song = Connection::open(dir.join("song.db"))?;
+
+		score.execute_batch(
+			"CREATE TABLE score (
+				sha256 TEXT, mode INTEGER, clear INTEGER, date INTEGER,
+				epg INTEGER, lpg INTEGER, egr INTEGER, lgr INTEGER, egd INTEGER, lgd INTEGER,
+				ebd INTEGER, lbd INTEGER, epr INTEGER, lpr INTEGER, ems INTEGER, lms INTEGER,
+				combo INTEGER, minbp INTEGER, random INTEGER,
+				PRIMARY KEY (sha256, mode)
+			);
+			BEGIN;",
+		)?;
+		songdata.execute_batch(
+			"CREATE TABLE song (
+				md5 TEXT, sha256 TEXT, title TEXT, subtitle TEXT, mode INTEGER, feature INTEGER,
+				path TEXT PRIMARY KEY
+			);
+			BEGIN;",
+		)?;
+		lr2score.execute_batch(
+			"CREATE TABLE score (
+				hash TEXT PRIMARY KEY, clear INTEGER, perfect INTEGER, great INTEGER,
+				good INTEGER, bad INTEGER, poor INTEGER, maxcombo INTEGER, minbp INTEGER,
+				op_best INTEGER, complete INTEGER
+			);
+			BEGIN;",
+		)?;
+		song.execute_batch(
+			"CREATE TABLE song (hash TEXT, title TEXT, subtitle TEXT, mode INTEGER, path TEXT);
+			BEGIN;",
+		)?;
+
+		for i in 0..scores {
+			let sha256 = format!("{i:064x}");
+			let md5 = format!("{i:032x}");
+			let title = format!("Song {i}");
+			let mode = if i % 7 == 0 { 14 } else { 7 };
+
+			// Leave some charts out, so the "chart not found" path gets exercised too.
+			if i % 10 != 9 {
+				songdata.execute(
+					"INSERT INTO song VALUES (?1, ?2, ?3, '[ANOTHER]', ?4, 0, ?5)",
+					(&md5, &sha256, &title, mode, format!("songs/{i}.bms")),
+				)?;
+				song.execute(
+					"INSERT INTO song VALUES (?1, ?2, '[ANOTHER]', ?3, ?4)",
+					(&md5, &title, mode, format!("songs/{i}.bms")),
+				)?;
+			}
+
+			score.execute(
+				"INSERT INTO score VALUES
+				(?1, 0, ?2, ?3, 400, 400, 100, 100, 10, 10, 5, 5, 5, 5, 0, 0, 900, 20, ?4)",
+				(
+					&sha256,
+					(i % 10) as i32 + 1,
+					1_600_000_000 + i as i64,
+					(i % 5) as i32,
+				),
+			)?;
+			lr2score.execute(
+				"INSERT INTO score VALUES (?1, ?2, 800, 200, 20, 10, 10, 900, 20, ?3, 1)",
+				(&md5, (i % 5) as i32 + 1, (i % 4) as i32 * 10),
+			)?;
+		}
+
+		for db in [&score, &songdata, &lr2score, &song] {
+			db.execute_batch("COMMIT;")?;
+		}
+
+		Ok(Self { dir })
+	}
+
+	fn path(&self, file: &str) -> PathBuf {
+		self.dir.join(file)
+	}
+}
+
+impl Drop for Fixture {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.dir);
+	}
+}
+
+/// What the converters did before: a prepared statement and a query per score.
+fn per_score_lookup(
+	score_path: &Path,
+	chart_path: &Path,
+	scores_sql: &str,
+	chart_sql: &str,
+) -> rusqlite::Result<usize> {
+	let score_db = Connection::open(score_path)?;
+	let chart_db = Connection::open(chart_path)?;
+
+	let mut stmt = score_db.prepare(scores_sql)?;
+	let hashes = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+	let mut found = 0;
+
+	for hash in hashes {
+		let mut chart_query = chart_db.prepare(chart_sql)?;
+
+		let chart = chart_query
+			.query_row([hash?], |row| row.get::<_, String>(0))
+			.optional()?;
+
+		if chart.is_some() {
+			found += 1;
+		}
+	}
+
+	Ok(found)
+}
+
+fn bench(name: &str, runs: u32, mut f: impl FnMut()) -> Duration {
+	let start = Instant::now();
+	for _ in 0..runs {
+		f();
+	}
+	let mean = start.elapsed() / runs;
+
+	println!("{name:<32} {mean:>12.2?}");
+
+	mean
+}
+
+fn main() {
+	let scores = env::var("TIS_BENCH_SCORES")
+		.ok()
+		.and_then(|e| e.parse().ok())
+		.unwrap_or(50_000);
+
+	println!("Generating a library of {scores} scores...");
+	let fixture = Fixture::generate(scores).expect("couldn't generate fixture");
+
+	let ctx = ConvertContext::new(Logger::new(StderrSink {
+		min_level: SerializableLevel::Error,
+	}));
+
+	let beatoraja = BeatorajaConfig {
+		score_path: fixture.path("score.db"),
+		chart_path: fixture.path("songdata.db"),
+		play_history: false,
+		scorelog_bests: false,
+		ln_modes: default_ln_modes(),
+		pms_playtype: None,
+	};
+	let lr2 = LR2Config {
+		score_path: fixture.path("lr2score.db"),
+		chart_path: fixture.path("song.db"),
+		pms_playtype: None,
+		course_path: None,
+	};
+
+	let before = bench("beatoraja: per-score lookup", 1, || {
+		per_score_lookup(
+			&beatoraja.score_path,
+			&beatoraja.chart_path,
+			"SELECT sha256 FROM score WHERE mode = 0",
+			"SELECT title FROM song WHERE sha256 = ?1",
+		)
+		.expect("lookup failed");
+	});
+	let after = bench("beatoraja: convert (joined)", RUNS, || {
+		Beatoraja::convert(&beatoraja, &ctx).expect("convert failed");
+	});
+	println!(
+		"{:<32} {:>11.1}x",
+		"",
+		before.as_secs_f64() / after.as_secs_f64()
+	);
+
+	let before = bench("lr2: per-score lookup", 1, || {
+		per_score_lookup(
+			&lr2.score_path,
+			&lr2.chart_path,
+			"SELECT hash FROM score WHERE complete = 1",
+			"SELECT title FROM song WHERE hash = ?1",
+		)
+		.expect("lookup failed");
+	});
+	let after = bench("lr2: convert (joined)", RUNS, || {
+		LR2::convert(&lr2, &ctx).expect("convert failed");
+	});
+	println!(
+		"{:<32} {:>11.1}x",
+		"",
+		before.as_secs_f64() / after.as_secs_f64()
+	);
+}
@@ -1,9 +1,24 @@
+pub mod auth;
 pub mod batch_manual;
 pub mod beatoraja;
 pub mod bms;
+pub mod checkpoint;
+pub mod client;
 pub mod config;
+pub mod courses;
+pub mod history;
+pub mod import;
 pub mod ipc;
+pub mod jobs;
 pub mod log;
 pub mod lr2;
+pub mod oauth;
+pub mod queue;
+pub mod report;
+pub mod source;
 pub mod sqlite;
+pub mod store;
+pub mod upload;
 pub mod usc;
+pub mod validate;
+pub mod watch;
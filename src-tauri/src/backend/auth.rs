@@ -0,0 +1,139 @@
+//! Checking who a token belongs to and what it's allowed to do, before relying on it.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use reqwest::{Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+	client::{is_offline, ClientOptions, Retry, TachiClient},
+	jobs::{JobHandle, JobKind},
+	log,
+};
+
+/// What a token needs to be allowed to import scores.
+pub const SUBMIT_SCORE: &str = "submit_score";
+
+/// How long the check before an import waits for the server. It isn't retried, so a server
+/// that's down only holds the import up this long before it's queued.
+const PRE_IMPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiResponse<T> {
+	success: bool,
+	description: String,
+	body: Option<T>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StatusBody {
+	/// The user the token belongs to, if it belongs to anyone.
+	whoami: Option<u64>,
+	#[serde(default)]
+	permissions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct UserBody {
+	username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthStatus {
+	/// Name of the server profile that was checked.
+	pub server: String,
+	#[serde(rename = "userID")]
+	pub user_id: u64,
+	pub username: String,
+	pub permissions: Vec<String>,
+	pub can_submit: bool,
+}
+
+async fn get<T: DeserializeOwned>(client: &TachiClient, url: &str) -> anyhow::Result<T> {
+	let name = &client.server().name;
+
+	let res = client
+		.send(
+			|| client.request(Method::GET, url),
+			Retry::Idempotent,
+			client.deadline(),
+			&JobHandle::detached(JobKind::Import),
+		)
+		.await?;
+
+	if matches!(
+		res.status(),
+		StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+	) {
+		bail!("Your token for {name} is invalid or has been revoked.");
+	}
+
+	let json: ApiResponse<T> = res
+		.json()
+		.await
+		.map_err(|e| anyhow!("Invalid response from {name} ({e:?})."))?;
+
+	match json.body {
+		Some(body) if json.success => Ok(body),
+		_ => bail!("{name} said: {}", json.description),
+	}
+}
+
+/// Who `client`'s token belongs to, and what it's allowed to do.
+pub async fn check_auth(client: &TachiClient) -> anyhow::Result<AuthStatus> {
+	let name = &client.server().name;
+
+	let status: StatusBody = get(client, "/api/v1/status").await?;
+
+	let Some(user_id) = status.whoami else {
+		bail!("Your token for {name} is invalid or has been revoked.");
+	};
+
+	let user: UserBody = get(client, "/api/v1/users/me").await?;
+
+	let can_submit = status.permissions.iter().any(|e| e == SUBMIT_SCORE);
+
+	if !can_submit {
+		log::warn(format!(
+			"Your token for {name} doesn't have the {SUBMIT_SCORE} permission, so it can't import scores. Make a new one that does."
+		));
+	}
+
+	Ok(AuthStatus {
+		server: name.clone(),
+		user_id,
+		username: user.username,
+		permissions: status.permissions,
+		can_submit,
+	})
+}
+
+/// Fail early if `client`'s token can't be used to import.
+///
+/// If the server can't be reached quickly this passes, so the import can be queued for later.
+pub async fn check_can_import(client: &TachiClient) -> anyhow::Result<()> {
+	let quick = client.with_options(ClientOptions {
+		connect_timeout: PRE_IMPORT_TIMEOUT,
+		request_timeout: PRE_IMPORT_TIMEOUT,
+		max_retries: 0,
+		..client.options().clone()
+	})?;
+
+	match check_auth(&quick).await {
+		Ok(status) if status.can_submit => {
+			log::info(format!(
+				"Importing to {} as {}.",
+				status.server, status.username
+			));
+			Ok(())
+		}
+		Ok(status) => bail!(
+			"Your token for {} can't import scores, it's missing the {SUBMIT_SCORE} permission.",
+			status.server
+		),
+		Err(err) if is_offline(&err) => Ok(()),
+		Err(err) => Err(err),
+	}
+}
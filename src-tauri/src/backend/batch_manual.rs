@@ -9,7 +9,7 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
-use super::bms::BMSRandom;
+use super::{bms::BMSRandom, config::SERVICE_NAME};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(transparent)]
@@ -24,6 +24,17 @@ pub struct BatchManualMeta {
 	pub version: Option<String>,
 }
 
+impl BatchManualMeta {
+	pub fn new(game: &str, playtype: &str) -> Self {
+		Self {
+			game: game.into(),
+			playtype: playtype.into(),
+			service: SERVICE_NAME.into(),
+			version: None,
+		}
+	}
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BMSBatchManual {
@@ -32,6 +43,14 @@ pub struct BMSBatchManual {
 	pub classes: BatchManualClasses,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PMSBatchManual {
+	pub meta: BatchManualMeta,
+	pub scores: Vec<PMSBatchManualScore>,
+	pub classes: BatchManualClasses,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct USCBatchManual {
@@ -144,6 +163,106 @@ pub struct BMSJudgements {
 	pub poor: Option<i32>,
 }
 
+/// PMS is converted from the same databases as BMS, so its scores start out as BMS scores
+/// and get renamed to pop'n terms at the end.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PMSBatchManualScore {
+	pub identifier: String,
+	pub match_type: String,
+	pub score: u64,
+	pub lamp: BMSLamp,
+	pub comment: Option<String>,
+	pub time_achieved: Option<i64>,
+	pub optional: Option<PMSOptionalMetrics>,
+	pub score_meta: Option<PMSScoreMeta>,
+	pub judgements: Option<PMSJudgements>,
+}
+
+/// Tachi names the early/late judgement counts after pop'n's judgements for PMS.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PMSOptionalMetrics {
+	pub fast: Option<i32>,
+	pub slow: Option<i32>,
+	pub max_combo: Option<i32>,
+	pub bp: Option<i32>,
+	pub gauge: Option<f64>,
+	pub gauge_history: Option<Vec<f64>>,
+	pub ecool: Option<i32>,
+	pub egreat: Option<i32>,
+	pub egood: Option<i32>,
+	pub ebad: Option<i32>,
+	pub epoor: Option<i32>,
+	pub lcool: Option<i32>,
+	pub lgreat: Option<i32>,
+	pub lgood: Option<i32>,
+	pub lbad: Option<i32>,
+	pub lpoor: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PMSScoreMeta {
+	pub random: Option<BMSRandom>,
+	pub gauge: Option<BMSGauge>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PMSJudgements {
+	pub cool: Option<i32>,
+	pub great: Option<i32>,
+	pub good: Option<i32>,
+	pub bad: Option<i32>,
+	pub poor: Option<i32>,
+}
+
+impl From<BMSBatchManualScore> for PMSBatchManualScore {
+	fn from(score: BMSBatchManualScore) -> Self {
+		Self {
+			identifier: score.identifier,
+			match_type: match score.match_type.as_str() {
+				"bmsChartHash" => "pmsChartHash".into(),
+				_ => score.match_type,
+			},
+			score: score.score,
+			lamp: score.lamp,
+			comment: score.comment,
+			time_achieved: score.time_achieved,
+			optional: score.optional.map(|e| PMSOptionalMetrics {
+				fast: e.fast,
+				slow: e.slow,
+				max_combo: e.max_combo,
+				bp: e.bp,
+				gauge: e.gauge,
+				gauge_history: e.gauge_history,
+				ecool: e.epg,
+				egreat: e.egr,
+				egood: e.egd,
+				ebad: e.ebd,
+				epoor: e.epr,
+				lcool: e.lpg,
+				lgreat: e.lgr,
+				lgood: e.lgd,
+				lbad: e.lbd,
+				lpoor: e.lpr,
+			}),
+			score_meta: score.score_meta.map(|e| PMSScoreMeta {
+				random: e.random,
+				gauge: e.gauge,
+			}),
+			judgements: score.judgements.map(|e| PMSJudgements {
+				cool: e.pgreat,
+				great: e.great,
+				good: e.good,
+				bad: e.bad,
+				poor: e.poor,
+			}),
+		}
+	}
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct USCJudgements {
@@ -217,3 +336,17 @@ pub struct USCBatchManualScore {
 	pub score_meta: Option<USCScoreMeta>,
 	pub judgements: Option<USCJudgements>,
 }
+
+/// Pull every batch manual out of some converter output. BMS converters return `{ k7, k14, pms }`,
+/// so this looks through objects and arrays for anything with a `meta` key.
+pub fn find_batch_manuals(value: serde_json::Value) -> Vec<serde_json::Value> {
+	if value.get("meta").is_some() {
+		return vec![value];
+	}
+
+	match value {
+		serde_json::Value::Object(map) => map.into_values().flat_map(find_batch_manuals).collect(),
+		serde_json::Value::Array(arr) => arr.into_iter().flat_map(find_batch_manuals).collect(),
+		_ => vec![],
+	}
+}
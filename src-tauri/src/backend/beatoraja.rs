@@ -1,5 +1,8 @@
+use std::path::Path;
+
 use bitflags::bitflags;
-use rusqlite::OptionalExtension;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
 
 use crate::backend::{
 	batch_manual::{BMSBatchManualScore, BMSClient, BMSLamp, BMSOptionalMetrics, BMSScoreMeta},
@@ -7,17 +10,32 @@ use crate::backend::{
 };
 
 use super::{
-	batch_manual::{BMSBatchManual, BMSJudgements, BatchManualClasses, BatchManualMeta},
+	batch_manual::BMSJudgements,
 	bms::{BMSConvertResults, BMSGamemode},
-	config::{BeatorajaConfig, SERVICE_NAME},
-	log,
-	sqlite::connect_sqlite3,
+	config::{BeatorajaConfig, TISConfig},
+	courses::{CourseClears, CourseHashes},
+	report::{ConversionReport, Converted, SkipReason},
+	source::{ConvertContext, ScoreSource},
+	sqlite::table_has_columns,
 };
 
 #[derive(Debug, Clone)]
 pub struct ScoreRow {
 	sha256: String,
+	/// The LN mode this was played with, if the chart left it up to the player.
+	mode: i32,
 	clear: i32,
+	/// `scorelog` only has the EX score.
+	judgements: Option<JudgementRow>,
+	exscore: i32,
+	combo: i32,
+	minbp: i32,
+	random: Option<i32>,
+	date: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct JudgementRow {
 	epg: i32,
 	egr: i32,
 	egd: i32,
@@ -30,10 +48,6 @@ pub struct ScoreRow {
 	lbd: i32,
 	lpr: i32,
 	lms: i32,
-	combo: i32,
-	minbp: i32,
-	random: i32,
-	date: i32,
 }
 
 pub struct ChartRow {
@@ -56,116 +70,347 @@ bitflags! {
 	}
 }
 
+/// How a chart's long notes are played, for charts that leave it up to the player
+/// ([`ChartFeatures::UNDEFINEDLN`]). beatoraja keeps a separate best for each.
+///
+/// Charts that declare their long note type (`LONGNOTE`, `CHARGENOTE`, `HELLCHARGENOTE`)
+/// always play the same way, and their scores are always under LN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LNMode {
+	#[serde(rename = "LN")]
+	LongNote,
+	#[serde(rename = "CN")]
+	ChargeNote,
+	#[serde(rename = "HCN")]
+	HellChargeNote,
+}
+
+impl LNMode {
+	/// The `mode` column of the score tables.
+	fn from_db(mode: i32) -> Option<Self> {
+		match mode {
+			0 => Some(LNMode::LongNote),
+			1 => Some(LNMode::ChargeNote),
+			2 => Some(LNMode::HellChargeNote),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			LNMode::LongNote => "LN",
+			LNMode::ChargeNote => "CN",
+			LNMode::HellChargeNote => "HCN",
+		}
+	}
+}
+
+/// Where scores are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScoreTable {
+	/// Each chart's best.
+	Best,
+	/// Every play, in full. Only newer builds have `scoredatalog.db`.
+	DataLog,
+	/// Each new best, as it stood after the play that set it. Only the EX score, lamp, combo
+	/// and bp. Only read when asked for, since it isn't the plays themselves.
+	Log,
+}
+
+impl ScoreTable {
+	fn name(self) -> &'static str {
+		match self {
+			ScoreTable::Best => "score",
+			ScoreTable::DataLog => "datalog.scoredatalog",
+			ScoreTable::Log => "scorelog",
+		}
+	}
+
+	/// Pick the most complete table there is, attaching `scoredatalog.db` from next to
+	/// `score_path` if it's there. `scorelog` is only picked if `scorelog_bests` allows it.
+	fn pick(
+		db: &mut Connection,
+		score_path: &Path,
+		play_history: bool,
+		scorelog_bests: bool,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<Self> {
+		if !play_history {
+			return Ok(ScoreTable::Best);
+		}
+
+		let datalog_path = score_path.with_file_name("scoredatalog.db");
+
+		if datalog_path.exists() {
+			ctx.attach_db(db, &datalog_path, "datalog")?;
+			ctx.log
+				.info(format!("Reading play history from {datalog_path:?}."));
+			return Ok(ScoreTable::DataLog);
+		}
+
+		if !scorelog_bests {
+			ctx.log.warn(format!(
+				"No play history, as there's no {datalog_path:?}. Only sending bests."
+			));
+			return Ok(ScoreTable::Best);
+		}
+
+		if table_has_columns(db, "scorelog", &["sha256", "clear", "score", "date"]) {
+			ctx.log.info(
+				"No scoredatalog.db, sending every new best from scorelog instead of plays.".into(),
+			);
+			return Ok(ScoreTable::Log);
+		}
+
+		ctx.log
+			.warn("No play history or scorelog in this database, only sending bests.".into());
+		Ok(ScoreTable::Best)
+	}
+}
+
+/// Course clears only count for dans on a normal gauge or better.
+const COURSE_CLEAR: i32 = 5;
+
+pub struct Beatoraja;
+
+impl ScoreSource for Beatoraja {
+	const ID: &'static str = "beatoraja";
+
+	type Config = BeatorajaConfig;
+	type Output = BMSConvertResults;
+
+	fn detect(db: &Connection) -> bool {
+		table_has_columns(db, "score", &["sha256", "epg", "lpg", "random"])
+	}
+
+	fn convert(
+		config: &BeatorajaConfig,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<Converted<BMSConvertResults>> {
+		convert_beatoraja_db(config, ctx)
+	}
+
+	fn saved_config(config: &mut TISConfig) -> &mut Option<BeatorajaConfig> {
+		&mut config.beatoraja_db
+	}
+
+	fn score_db(config: &BeatorajaConfig) -> Option<&Path> {
+		Some(&config.score_path)
+	}
+}
+
 pub fn convert_beatoraja_db(
 	BeatorajaConfig {
 		chart_path,
 		score_path,
+		pms_playtype,
+		play_history,
+		scorelog_bests,
+		ln_modes,
 	}: &BeatorajaConfig,
-) -> anyhow::Result<BMSConvertResults> {
-	let score_db = connect_sqlite3(score_path)?;
-	let chart_db = connect_sqlite3(chart_path)?;
+	ctx: &ConvertContext,
+) -> anyhow::Result<Converted<BMSConvertResults>> {
+	let log = &ctx.log;
+	let mut report = ConversionReport {
+		chart_db: chart_path
+			.file_name()
+			.map(|e| e.to_string_lossy().into_owned()),
+		..Default::default()
+	};
+
+	let mut db = ctx.open_with_charts(score_path, chart_path)?;
+	let table = ScoreTable::pick(&mut db, score_path, *play_history, *scorelog_bests, ctx)?;
+
+	let mut courses = CourseClears::new(
+		&db,
+		ctx.courses.iter().cloned(),
+		CourseHashes::Sha256,
+		COURSE_CLEAR,
+		log,
+	)?;
 
-	let mut db_scores = score_db.prepare("SELECT * FROM score WHERE mode = 0")?;
+	// songdata.db has a row per chart *file*, so copies of the same chart in different
+	// folders share a sha256. Collapse those before joining.
+	let mut db_scores = db.prepare(&format!(
+		"
+		SELECT
+			score.*,
+			song.sha256 AS chart_sha256,
+			song.title AS chart_title,
+			song.subtitle AS chart_subtitle,
+			song.feature AS chart_feature,
+			song.mode AS chart_mode
+		FROM
+			{} AS score
+			LEFT JOIN (
+				SELECT sha256, title, subtitle, feature, mode FROM chart.song GROUP BY sha256
+			) AS song ON song.sha256 = score.sha256
+		WHERE
+			score.mode IN (0, 1, 2)
+	",
+		table.name()
+	))?;
 
 	let scores = db_scores.query_map([], |row| {
-		Ok(ScoreRow {
+		let chart = match row.get::<_, Option<String>>("chart_sha256")? {
+			Some(_) => Some(ChartRow {
+				feature: ChartFeatures::from_bits_truncate(
+					row.get::<_, i32>("chart_feature")? as u32
+				),
+				mode: BMSGamemode::from_keys(row.get("chart_mode")?),
+				subtitle: row.get("chart_subtitle")?,
+				title: row.get("chart_title")?,
+			}),
+			None => None,
+		};
+
+		let judgements = match table {
+			ScoreTable::Log => None,
+			ScoreTable::Best | ScoreTable::DataLog => Some(JudgementRow {
+				epg: row.get("epg")?,
+				egr: row.get("egr")?,
+				egd: row.get("egd")?,
+				ebd: row.get("ebd")?,
+				epr: row.get("epr")?,
+				ems: row.get("ems")?,
+				lpg: row.get("lpg")?,
+				lgr: row.get("lgr")?,
+				lgd: row.get("lgd")?,
+				lbd: row.get("lbd")?,
+				lpr: row.get("lpr")?,
+				lms: row.get("lms")?,
+			}),
+		};
+
+		let score = ScoreRow {
 			sha256: row.get("sha256")?,
+			mode: row.get("mode")?,
 			clear: row.get("clear")?,
-			epg: row.get("epg")?,
-			egr: row.get("egr")?,
-			egd: row.get("egd")?,
-			ebd: row.get("ebd")?,
-			epr: row.get("epr")?,
-			ems: row.get("ems")?,
-			lpg: row.get("lpg")?,
-			lgr: row.get("lgr")?,
-			lgd: row.get("lgd")?,
-			lbd: row.get("lbd")?,
-			lpr: row.get("lpr")?,
-			lms: row.get("lms")?,
+			exscore: match &judgements {
+				Some(j) => (j.lpg + j.epg) * 2 + j.egr + j.lgr,
+				None => row.get("score")?,
+			},
+			judgements,
 			combo: row.get("combo")?,
 			minbp: row.get("minbp")?,
-			random: row.get("random")?,
+			random: match table {
+				ScoreTable::Log => None,
+				ScoreTable::Best | ScoreTable::DataLog => Some(row.get("random")?),
+			},
 			date: row.get("date")?,
-		})
+		};
+
+		Ok((score, chart))
 	})?;
 
 	let mut scores_7k = vec![];
 	let mut scores_14k = vec![];
+	let mut scores_pms = vec![];
 
-	for score in scores {
-		let score = match score {
+	for (i, row) in scores.enumerate() {
+		ctx.row_scanned(i + 1, scores_7k.len() + scores_14k.len() + scores_pms.len())?;
+
+		let (score, chart) = match row {
 			Ok(v) => v,
 			Err(err) => {
-				log::warn(format!("Invalid score in DB: {err}. Skipping."));
+				log.debug(format!("Invalid score in DB: {err}. Skipping."));
+				report.skip(SkipReason::InvalidRow, None, None);
 				continue;
 			}
 		};
-		let mut chart_query = chart_db.prepare(
-			"
-			SELECT
-				title, subtitle, feature, notes, mode
-			FROM
-				song
-			WHERE
-				sha256 = ?1
-		",
-		)?;
-
-		let chart = chart_query
-			.query_row([&score.sha256], |row| {
-				Ok(ChartRow {
-					feature: ChartFeatures::from_bits_truncate(row.get::<_, i32>("feature")? as u32),
-					mode: match row.get::<_, i32>("mode")? {
-						7 => Some(BMSGamemode::SevenKey),
-						14 => Some(BMSGamemode::FourteenKey),
-						_ => None,
-					},
-					subtitle: row.get("subtitle")?,
-					title: row.get("title")?,
-				})
-			})
-			.optional()?;
+
+		let is_course = score.sha256.len() > 64 && score.sha256.len() % 64 == 0;
+
+		if chart.is_none() && is_course {
+			courses.record(&score.sha256, &score.sha256, score.clear, &mut report, log);
+			continue;
+		}
 
 		let Some(chart) = chart else {
-			log::warn(format!(
+			log.debug(format!(
 				"Couldn't find a matching chart for score {}",
 				score.sha256
 			));
+			report.skip(SkipReason::ChartNotFound, Some(&score.sha256), None);
 			continue;
 		};
 
 		let name = format!("{} {}", chart.title, chart.subtitle);
 
 		if chart.feature.contains(ChartFeatures::RANDOM) {
-			log::info(format!("Skipping {name} as it has #RANDOM declarations."));
+			log.debug(format!("Skipping {name} as it has #RANDOM declarations."));
+			report.skip(SkipReason::RandomChart, Some(&score.sha256), Some(&name));
 			continue;
 		}
 
 		let Some(mode) = chart.mode else {
-			log::debug(format!("Skipping unknown gamemode for {name}"));
+			log.debug(format!("Skipping unknown gamemode for {name}"));
+			report.skip(
+				SkipReason::UnknownGamemode,
+				Some(&score.sha256),
+				Some(&name),
+			);
+			continue;
+		};
+
+		let Some(playtype) = mode.playtype(*pms_playtype) else {
+			log.debug(format!(
+				"Skipping PMS score on {name}, as no PMS playtype was picked."
+			));
+			report.skip(SkipReason::NoPmsPlaytype, Some(&score.sha256), Some(&name));
+			continue;
+		};
+
+		let ln_mode = if chart.feature.contains(ChartFeatures::UNDEFINEDLN) {
+			LNMode::from_db(score.mode)
+		} else if score.mode == 0 {
+			None
+		} else {
+			log.debug(format!(
+				"Skipping score on {name} in LN mode {}, as the chart doesn't use it.",
+				score.mode
+			));
+			report.skip(SkipReason::LnModeUnused, Some(&score.sha256), Some(&name));
 			continue;
 		};
 
+		if let Some(ln_mode) = ln_mode.filter(|e| !ln_modes.contains(e)) {
+			log.debug(format!(
+				"Skipping {} score on {name}, as {} scores weren't picked.",
+				ln_mode.name(),
+				ln_mode.name()
+			));
+			report.skip(
+				SkipReason::LnModeNotPicked,
+				Some(&score.sha256),
+				Some(&name),
+			);
+			continue;
+		}
+
+		if !ctx.is_new(playtype, score.date as i64 * 1000) {
+			report.already_imported += 1;
+			continue;
+		}
+
 		let mut random = None;
 
-		match mode {
-			BMSGamemode::SevenKey => {
-				random = Some(match score.random {
-					0 => BMSRandom::Nonran,
-					1 => BMSRandom::Mirror,
-					2 => BMSRandom::Random,
-					3 => BMSRandom::RRandom,
-					4 => BMSRandom::SRandom,
-					_unknown => {
-						log::warn(format!(
-							"Skipping score on {name} as the random was invalid or unfair (H-Ran, Spiral, etc.)"
-						));
-						continue;
-					}
-				})
-			}
-			BMSGamemode::FourteenKey => {}
+		// scorelog doesn't say what random was used.
+		if let Some(option) = score.random.filter(|_| mode.has_random()) {
+			random = Some(match option {
+				0 => BMSRandom::Nonran,
+				1 => BMSRandom::Mirror,
+				2 => BMSRandom::Random,
+				3 => BMSRandom::RRandom,
+				4 => BMSRandom::SRandom,
+				_unknown => {
+					log.debug(format!(
+						"Skipping score on {name} as the random was invalid or unfair (H-Ran, Spiral, etc.)"
+					));
+					report.skip(SkipReason::InvalidRandom, Some(&score.sha256), Some(&name));
+					continue;
+				}
+			})
 		}
 
 		// Various beatoraja fuckery abound here.
@@ -175,46 +420,52 @@ pub fn convert_beatoraja_db(
 			Some(score.minbp)
 		};
 
+		let lamp = match score.clear {
+			0 => BMSLamp::NoPlay,
+			1 => BMSLamp::Failed,
+			2 => BMSLamp::AssistClear,
+			3 => BMSLamp::AssistClear,
+			4 => BMSLamp::EasyClear,
+			5 => BMSLamp::Clear,
+			6 => BMSLamp::HardClear,
+			7 => BMSLamp::ExHardClear,
+			8..=10 => BMSLamp::FullCombo,
+			invalid => {
+				log.debug(format!(
+					"Invalid lamp on {name} -- got {invalid}; ignoring."
+				));
+				report.skip(SkipReason::InvalidLamp, Some(&score.sha256), Some(&name));
+				continue;
+			}
+		};
+
+		let j = score.judgements.as_ref();
+
 		let score = BMSBatchManualScore {
-			comment: None,
+			// Tachi has nowhere else to keep it.
+			comment: ln_mode.map(|e| format!("{} mode", e.name())),
 			identifier: score.sha256,
 			match_type: "bmsChartHash".into(),
-			score: ((score.lpg + score.epg) * 2 + score.egr + score.lgr) as u64,
-			lamp: match score.clear {
-				0 => BMSLamp::NoPlay,
-				1 => BMSLamp::Failed,
-				2 => BMSLamp::AssistClear,
-				3 => BMSLamp::AssistClear,
-				4 => BMSLamp::EasyClear,
-				5 => BMSLamp::Clear,
-				6 => BMSLamp::HardClear,
-				7 => BMSLamp::ExHardClear,
-				8..=10 => BMSLamp::FullCombo,
-				invalid => {
-					log::warn(format!(
-						"Invalid lamp on {name} -- got {invalid}; ignoring."
-					));
-					continue;
-				}
-			},
+			score: score.exscore as u64,
+			lamp,
 			time_achieved: Some(score.date as i64 * 1000),
 			optional: Some(BMSOptionalMetrics {
 				bp,
-				fast: Some(score.egr + score.egd),
-				slow: Some(score.lgr + score.lgd),
+				fast: j.map(|j| j.egr + j.egd),
+				slow: j.map(|j| j.lgr + j.lgd),
 				max_combo: Some(score.combo),
 				gauge: None,
 				gauge_history: None,
-				epg: Some(score.epg),
-				egr: Some(score.egr),
-				egd: Some(score.egd),
-				ebd: Some(score.ebd),
-				epr: Some(score.epr),
-				lpg: Some(score.lpg),
-				lgr: Some(score.lgr),
-				lgd: Some(score.lgd),
-				lbd: Some(score.lbd),
-				lpr: Some(score.lpr),
+				epg: j.map(|j| j.epg),
+				egr: j.map(|j| j.egr),
+				egd: j.map(|j| j.egd),
+				ebd: j.map(|j| j.ebd),
+				epr: j.map(|j| j.epr),
+				lpg: j.map(|j| j.lpg),
+				lgr: j.map(|j| j.lgr),
+				lgd: j.map(|j| j.lgd),
+				lbd: j.map(|j| j.lbd),
+				lpr: j.map(|j| j.lpr),
 			}),
 			score_meta: Some(BMSScoreMeta {
 				random,
@@ -222,12 +473,12 @@ pub fn convert_beatoraja_db(
 				client: Some(BMSClient::Lr2oraja),
 				gauge: None,
 			}),
-			judgements: Some(BMSJudgements {
-				pgreat: Some(score.epg + score.lpg),
-				great: Some(score.egr + score.lgr),
-				good: Some(score.egd + score.lgd),
-				bad: Some(score.ebd + score.lbd),
-				poor: Some(score.epr + score.lpr + score.ems + score.lms),
+			judgements: j.map(|j| BMSJudgements {
+				pgreat: Some(j.epg + j.lpg),
+				great: Some(j.egr + j.lgr),
+				good: Some(j.egd + j.lgd),
+				bad: Some(j.ebd + j.lbd),
+				poor: Some(j.epr + j.lpr + j.ems + j.lms),
 			}),
 		};
 
@@ -238,43 +489,23 @@ pub fn convert_beatoraja_db(
 			BMSGamemode::FourteenKey => {
 				scores_14k.push(score);
 			}
+			BMSGamemode::NineKey => {
+				scores_pms.push(score);
+			}
 		}
 	}
 
-	let mut ret = BMSConvertResults {
-		k14: None,
-		k7: None,
-	};
-
-	if !scores_7k.is_empty() {
-		ret.k7 = Some(BMSBatchManual {
-			classes: BatchManualClasses::default(),
-			meta: BatchManualMeta {
-				game: "bms".into(),
-				playtype: "7K".into(),
-				service: SERVICE_NAME.into(),
-				version: None,
-			},
-			scores: scores_7k,
-		});
-	}
-
-	if !scores_14k.is_empty() {
-		ret.k14 = Some(BMSBatchManual {
-			classes: BatchManualClasses::default(),
-			meta: BatchManualMeta {
-				game: "bms".into(),
-				playtype: "14K".into(),
-				service: SERVICE_NAME.into(),
-				version: None,
-			},
-			scores: scores_14k,
-		});
-	}
-
-	if ret.k14.is_none() && ret.k7.is_none() {
-		log::warn("Converted no scores! Nothing will be uploaded.".into());
-	}
+	report.finish(scores_7k.len() + scores_14k.len() + scores_pms.len(), log);
 
-	Ok(ret)
+	Ok(Converted {
+		output: BMSConvertResults::from_scores(
+			scores_7k,
+			scores_14k,
+			scores_pms,
+			*pms_playtype,
+			courses.dans(),
+			log,
+		),
+		report,
+	})
 }
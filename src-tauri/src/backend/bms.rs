@@ -1,11 +1,64 @@
 use serde::{Deserialize, Serialize};
 
-use super::batch_manual::BMSBatchManual;
+use super::{
+	batch_manual::{
+		BMSBatchManual, BMSBatchManualScore, BatchManualClasses, BatchManualMeta, PMSBatchManual,
+		PMSBatchManualScore,
+	},
+	courses::Dans,
+	log::Logger,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BMSGamemode {
 	SevenKey,
 	FourteenKey,
+	/// PMS.
+	NineKey,
+}
+
+impl BMSGamemode {
+	/// Map the `mode` column of a chart database (the number of keys) to a gamemode.
+	pub fn from_keys(keys: i32) -> Option<Self> {
+		match keys {
+			7 => Some(Self::SevenKey),
+			9 => Some(Self::NineKey),
+			14 => Some(Self::FourteenKey),
+			_ => None,
+		}
+	}
+
+	/// The Tachi playtype for this gamemode. The databases don't say what PMS was played on,
+	/// so that's `pms`, and there's no playtype if it wasn't given.
+	pub fn playtype(&self, pms: Option<PMSPlaytype>) -> Option<&'static str> {
+		match self {
+			Self::SevenKey => Some("7K"),
+			Self::FourteenKey => Some("14K"),
+			Self::NineKey => pms.map(|e| e.playtype()),
+		}
+	}
+
+	/// Whether scores should have a random option. DP randoms are per side, which Tachi
+	/// doesn't have a way to store.
+	pub fn has_random(&self) -> bool {
+		matches!(self, Self::SevenKey | Self::NineKey)
+	}
+}
+
+/// What PMS scores were played on. Tachi keeps separate leaderboards for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PMSPlaytype {
+	Controller,
+	Keyboard,
+}
+
+impl PMSPlaytype {
+	pub fn playtype(&self) -> &'static str {
+		match self {
+			PMSPlaytype::Controller => "Controller",
+			PMSPlaytype::Keyboard => "Keyboard",
+		}
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,4 +79,58 @@ pub enum BMSRandom {
 pub struct BMSConvertResults {
 	pub k7: Option<BMSBatchManual>,
 	pub k14: Option<BMSBatchManual>,
+	pub pms: Option<PMSBatchManual>,
+}
+
+impl BMSConvertResults {
+	pub fn from_scores(
+		scores_7k: Vec<BMSBatchManualScore>,
+		scores_14k: Vec<BMSBatchManualScore>,
+		scores_pms: Vec<BMSBatchManualScore>,
+		pms_playtype: Option<PMSPlaytype>,
+		dans: &Dans,
+		log: &Logger,
+	) -> Self {
+		let mut ret = Self {
+			k14: None,
+			k7: None,
+			pms: None,
+		};
+
+		let classes_7k = dans.classes("7K");
+		let classes_14k = dans.classes("14K");
+
+		if !scores_7k.is_empty() || !classes_7k.0.is_empty() {
+			ret.k7 = Some(BMSBatchManual {
+				classes: classes_7k,
+				meta: BatchManualMeta::new("bms", "7K"),
+				scores: scores_7k,
+			});
+		}
+
+		if !scores_14k.is_empty() || !classes_14k.0.is_empty() {
+			ret.k14 = Some(BMSBatchManual {
+				classes: classes_14k,
+				meta: BatchManualMeta::new("bms", "14K"),
+				scores: scores_14k,
+			});
+		}
+
+		if let Some(playtype) = pms_playtype.filter(|_| !scores_pms.is_empty()) {
+			ret.pms = Some(PMSBatchManual {
+				classes: BatchManualClasses::default(),
+				meta: BatchManualMeta::new("pms", playtype.playtype()),
+				scores: scores_pms
+					.into_iter()
+					.map(PMSBatchManualScore::from)
+					.collect(),
+			});
+		}
+
+		if ret.k14.is_none() && ret.k7.is_none() && ret.pms.is_none() {
+			log.warn("Converted no scores! Nothing will be uploaded.".into());
+		}
+
+		ret
+	}
 }
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::store::{load_json, save_json};
+
+/// Playtype -> newest imported `timeAchieved`.
+type SourceCheckpoints = HashMap<String, i64>;
+
+/// The newest `timeAchieved` we've successfully imported, per server, source and playtype.
+///
+/// Conversions skip anything at or before this, so that a long-time player doesn't
+/// re-upload their entire history on every run. Each server gets its own, as importing to
+/// one says nothing about what another has.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Checkpoints {
+	/// Server name -> source -> checkpoints.
+	#[serde(default)]
+	servers: HashMap<String, HashMap<String, SourceCheckpoints>>,
+	/// Checkpoints from before server profiles, keyed by source.
+	#[serde(flatten)]
+	legacy: HashMap<String, SourceCheckpoints>,
+}
+
+impl Checkpoints {
+	const PATH: &str = "tis-checkpoints.json";
+
+	pub fn load() -> Self {
+		load_json(Self::PATH)
+	}
+
+	pub fn save(&self) {
+		save_json(Self::PATH, self);
+	}
+
+	/// Hand the checkpoints from before server profiles to `server`, the profile the old
+	/// config was turned into.
+	pub fn adopt_legacy(server: &str) {
+		let mut checkpoints = Self::load();
+
+		if checkpoints.legacy.is_empty() {
+			return;
+		}
+
+		let sources = checkpoints.servers.entry(server.to_owned()).or_default();
+
+		for (source, playtypes) in std::mem::take(&mut checkpoints.legacy) {
+			let existing = sources.entry(source).or_default();
+
+			for (playtype, time) in playtypes {
+				let checkpoint = existing.entry(playtype).or_insert(time);
+				*checkpoint = (*checkpoint).max(time);
+			}
+		}
+
+		checkpoints.save();
+	}
+
+	/// Playtype -> newest `timeAchieved` imported to `server` from this source.
+	pub fn for_source(&self, server: &str, source: &str) -> SourceCheckpoints {
+		self.servers
+			.get(server)
+			.and_then(|e| e.get(source))
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	/// Move the checkpoint for this batch manual's playtype up to its newest score.
+	pub fn record_import(&mut self, server: &str, source: &str, bm: &serde_json::Value) {
+		let Some(playtype) = bm.pointer("/meta/playtype").and_then(|e| e.as_str()) else {
+			return;
+		};
+
+		let newest = bm
+			.get("scores")
+			.and_then(|e| e.as_array())
+			.into_iter()
+			.flatten()
+			.filter_map(|e| e.get("timeAchieved").and_then(|e| e.as_i64()))
+			.max();
+
+		// LR2 doesn't store when scores were set, so there's nothing to go on.
+		let Some(newest) = newest else {
+			return;
+		};
+
+		let checkpoint = self
+			.servers
+			.entry(server.to_owned())
+			.or_default()
+			.entry(source.to_owned())
+			.or_default()
+			.entry(playtype.to_owned())
+			.or_insert(newest);
+
+		*checkpoint = (*checkpoint).max(newest);
+	}
+}
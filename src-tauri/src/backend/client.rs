@@ -0,0 +1,243 @@
+//! HTTP client for talking to a Tachi server. Handles auth, timeouts and retrying.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, Method, RequestBuilder, Response, StatusCode};
+
+use super::{
+	config::{ServerConfig, TISConfig},
+	jobs::JobHandle,
+	log,
+};
+
+const USER_AGENT: &str = "TIS/2.2.0";
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+	pub connect_timeout: Duration,
+	/// For a single request. Polls are quick, and a batch manual upload shouldn't take this long.
+	pub request_timeout: Duration,
+	pub max_retries: u32,
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+	/// How long a whole import (upload and polling) is allowed to take.
+	pub deadline: Duration,
+	/// Batch manuals bigger than this are uploaded as several imports.
+	pub max_chunk_bytes: usize,
+}
+
+impl Default for ClientOptions {
+	fn default() -> Self {
+		Self {
+			connect_timeout: Duration::from_secs(10),
+			request_timeout: Duration::from_secs(60),
+			max_retries: 5,
+			initial_backoff: Duration::from_secs(1),
+			max_backoff: Duration::from_secs(30),
+			deadline: Duration::from_secs(10 * 60),
+			max_chunk_bytes: 1024 * 1024,
+		}
+	}
+}
+
+impl ClientOptions {
+	fn backoff(&self, attempt: u32) -> Duration {
+		self.initial_backoff
+			.saturating_mul(2u32.saturating_pow(attempt))
+			.min(self.max_backoff)
+	}
+}
+
+/// Whether a request is safe to send twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+	/// Retry on network errors, timeouts, 5xx and 429.
+	Idempotent,
+	/// Only retry when the server definitely didn't act on it: it couldn't be connected to,
+	/// or it rate limited us.
+	NonIdempotent,
+}
+
+pub struct TachiClient {
+	http: reqwest::Client,
+	server: ServerConfig,
+	auth: String,
+	options: ClientOptions,
+}
+
+impl TachiClient {
+	pub fn new(server: &ServerConfig, auth: &str, options: ClientOptions) -> anyhow::Result<Self> {
+		let http = reqwest::Client::builder()
+			.connect_timeout(options.connect_timeout)
+			.timeout(options.request_timeout)
+			.user_agent(USER_AGENT)
+			.build()?;
+
+		Ok(Self {
+			http,
+			server: server.clone(),
+			auth: auth.to_owned(),
+			options,
+		})
+	}
+
+	/// A client for the server called `server`, or the active one, using its token and the
+	/// configured import deadline.
+	pub fn from_config(config: &TISConfig, server: Option<&str>) -> anyhow::Result<Self> {
+		let server = config.pick_server(server)?;
+
+		let Some(auth) = &server.auth_token else {
+			bail!("You have no auth token set up for {}.", server.name);
+		};
+
+		Self::new(
+			server,
+			auth,
+			ClientOptions {
+				deadline: Duration::from_secs(config.import_timeout_minutes * 60),
+				..Default::default()
+			},
+		)
+	}
+
+	pub fn server(&self) -> &ServerConfig {
+		&self.server
+	}
+
+	pub fn options(&self) -> &ClientOptions {
+		&self.options
+	}
+
+	/// The same server and token, with different options.
+	pub fn with_options(&self, options: ClientOptions) -> anyhow::Result<Self> {
+		Self::new(&self.server, &self.auth, options)
+	}
+
+	/// When an import started now has to be done by.
+	pub fn deadline(&self) -> Instant {
+		Instant::now() + self.options.deadline
+	}
+
+	/// The error for running past [`TachiClient::deadline`].
+	pub fn deadline_error(&self) -> anyhow::Error {
+		let secs = self.options.deadline.as_secs();
+		let waited = if secs >= 60 {
+			format!("{} minutes", secs / 60)
+		} else {
+			format!("{secs} seconds")
+		};
+
+		anyhow!(
+			"Gave up on {} after {waited}. The import may still finish, check your imports on the site.",
+			self.server.name,
+		)
+	}
+
+	/// An authenticated request to `url`. Relative URLs are relative to the server.
+	pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+		let url = if url.starts_with('/') {
+			format!("{}{url}", self.server.base_url)
+		} else {
+			url.to_owned()
+		};
+
+		self.http
+			.request(method, url)
+			.header("Authorization", format!("Bearer {}", self.auth))
+	}
+
+	/// Send the request made by `build`, retrying with exponential backoff as `retry` allows.
+	///
+	/// Any response that isn't retried is returned as is, so callers still have to check the
+	/// status. Cancelling `job` stops it straight away, even mid-request or while backing off.
+	pub async fn send(
+		&self,
+		build: impl Fn() -> RequestBuilder,
+		retry: Retry,
+		deadline: Instant,
+		job: &JobHandle,
+	) -> anyhow::Result<Response> {
+		let mut attempt = 0;
+
+		loop {
+			let res = job
+				.until_cancelled(async { Ok(build().send().await) })
+				.await?;
+
+			let (problem, wait) = match &res {
+				Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => (
+					"rate limited".to_owned(),
+					retry_after(res).unwrap_or_else(|| self.options.backoff(attempt)),
+				),
+				Ok(res) if retry == Retry::Idempotent && res.status().is_server_error() => {
+					(res.status().to_string(), self.options.backoff(attempt))
+				}
+				Err(err)
+					if err.is_connect() || (retry == Retry::Idempotent && err.is_timeout()) =>
+				{
+					(err.to_string(), self.options.backoff(attempt))
+				}
+				_ => return res.map_err(|e| request_error(e, "Request failed")),
+			};
+
+			if attempt >= self.options.max_retries {
+				let msg = format!(
+					"Request to {} failed {} times, giving up. Last error",
+					self.server.name,
+					attempt + 1
+				);
+
+				return Err(match res {
+					Ok(_) => anyhow!("{msg}: {problem}"),
+					Err(err) => request_error(err, &msg),
+				});
+			}
+
+			if Instant::now() + wait > deadline {
+				return Err(self.deadline_error());
+			}
+
+			log::warn(format!(
+				"Request to {} failed ({problem}), retrying in {}s.",
+				self.server.name,
+				wait.as_secs_f32()
+			));
+
+			job.until_cancelled(async {
+				tokio::time::sleep(wait).await;
+				Ok(())
+			})
+			.await?;
+			attempt += 1;
+		}
+	}
+}
+
+/// Keeps the [`reqwest::Error`] underneath, so [`is_offline`] can find it.
+fn request_error(err: reqwest::Error, msg: &str) -> anyhow::Error {
+	let msg = format!("{msg}: {err}");
+	anyhow::Error::new(err).context(msg)
+}
+
+/// Whether `err` came from not being able to reach the server at all, as opposed to the
+/// server turning us down.
+pub fn is_offline(err: &anyhow::Error) -> bool {
+	err.chain()
+		.filter_map(|e| e.downcast_ref::<reqwest::Error>())
+		.any(|e| e.is_connect() || e.is_timeout())
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn retry_after(res: &Response) -> Option<Duration> {
+	let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+	if let Ok(secs) = value.trim().parse::<u64>() {
+		return Some(Duration::from_secs(secs));
+	}
+
+	let at = DateTime::parse_from_rfc2822(value).ok()?;
+
+	(at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
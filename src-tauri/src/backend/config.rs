@@ -1,17 +1,33 @@
-use std::{fs, path::PathBuf};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
 
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
 use crate::backend::log;
 
-use super::usc::USCPlaytype;
+use super::{
+	beatoraja::LNMode, bms::PMSPlaytype, checkpoint::Checkpoints, queue, usc::USCPlaytype,
+};
 
+/// A Tachi server to import to. Profiles are told apart by name.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
 	pub name: String,
 	pub base_url: String,
 	pub client_url: String,
 	pub client_id: String,
+	/// Only needed for logging in through the browser.
+	#[serde(default)]
+	pub client_secret: Option<String>,
+	/// The port in the redirect URI the client was registered with. Also needed for logging in
+	/// through the browser.
+	#[serde(default)]
+	pub redirect_port: Option<u16>,
+	#[serde(default)]
+	pub auth_token: Option<String>,
 }
 
 impl Default for ServerConfig {
@@ -21,6 +37,9 @@ impl Default for ServerConfig {
 			base_url: "https://boku.tachi.ac".into(),
 			client_id: "CI18c4ebe4297a9e66960ad7b7bc88e91ace634ef8".into(),
 			client_url: "https://boku.tachi.ac".into(),
+			client_secret: None,
+			redirect_port: None,
+			auth_token: None,
 		}
 	}
 }
@@ -30,6 +49,12 @@ impl Default for ServerConfig {
 pub struct LR2Config {
 	pub score_path: PathBuf,
 	pub chart_path: PathBuf,
+	/// What PMS scores were played on. They're skipped if this isn't set.
+	#[serde(default)]
+	pub pms_playtype: Option<PMSPlaytype>,
+	/// An `.lr2crs` course file, or a folder of them, to look for dan courses in.
+	#[serde(default)]
+	pub course_path: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +62,24 @@ pub struct LR2Config {
 pub struct BeatorajaConfig {
 	pub score_path: PathBuf,
 	pub chart_path: PathBuf,
+	/// Send every play from the play log, instead of only each chart's best.
+	#[serde(default)]
+	pub play_history: bool,
+	/// With `play_history`, send `scorelog` if there's no `scoredatalog.db`. It doesn't have
+	/// plays, only each new best as it stood after the play that set it.
+	#[serde(default)]
+	pub scorelog_bests: bool,
+	/// Which LN modes to send scores from, for charts that leave it up to the player. Tachi
+	/// doesn't tell them apart, so picking more than one mixes them together.
+	#[serde(rename = "lnModes", default = "default_ln_modes")]
+	pub ln_modes: Vec<LNMode>,
+	/// What PMS scores were played on. They're skipped if this isn't set.
+	#[serde(default)]
+	pub pms_playtype: Option<PMSPlaytype>,
+}
+
+pub fn default_ln_modes() -> Vec<LNMode> {
+	vec![LNMode::LongNote]
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,8 +91,20 @@ pub struct USCConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TISConfig {
-	#[serde(default = "ServerConfig::default")]
-	pub server: ServerConfig,
+	#[serde(default)]
+	pub servers: Vec<ServerConfig>,
+	/// Name of the server imports go to unless told otherwise.
+	#[serde(rename = "activeServer", default)]
+	pub active_server: String,
+
+	/// Configs from before server profiles had one server, and the token for it here.
+	#[serde(default, skip_serializing)]
+	server: Option<ServerConfig>,
+	#[serde(rename = "authToken", default, skip_serializing)]
+	auth_token: Option<String>,
+	/// The profile an old config was just turned into, until [`TISConfig::finish_migration`].
+	#[serde(skip)]
+	migrated_to: Option<String>,
 
 	#[serde(rename = "lr2DB")]
 	pub lr2: Option<LR2Config>,
@@ -58,35 +113,50 @@ pub struct TISConfig {
 	#[serde(rename = "uscDB")]
 	pub usc_db: Option<USCConfig>,
 
-	#[serde(rename = "authToken")]
-	pub auth_token: Option<String>,
 	pub warning: String,
 
-	pub staging: Option<bool>,
+	/// Convert from a snapshot of each database, so the game can keep running during an import.
+	#[serde(rename = "snapshotDBs", default)]
+	pub snapshot_dbs: bool,
+
+	/// How long to wait for an import to finish before giving up on it.
+	#[serde(rename = "importTimeoutMinutes", default = "default_import_timeout")]
+	pub import_timeout_minutes: u64,
+}
+
+fn default_import_timeout() -> u64 {
+	10
 }
 
 impl Default for TISConfig {
 	fn default() -> Self {
+		let server = ServerConfig::default();
+
 		Self {
-			server: Default::default(),
+			active_server: server.name.clone(),
+			servers: vec![server],
+			server: None,
+			auth_token: None,
+			migrated_to: None,
 			lr2: Default::default(),
 			beatoraja_db: Default::default(),
 			usc_db: Default::default(),
-			auth_token: Default::default(),
 			warning: "THIS FILE WILL CONTAIN AN API AUTH KEY. DON'T SEND IT TO ANYONE!".into(),
-			staging: Default::default(),
+			snapshot_dbs: Default::default(),
+			import_timeout_minutes: default_import_timeout(),
 		}
 	}
 }
 
 impl TISConfig {
-	const PATH: &str = "tis-config.json";
+	pub const PATH: &str = "tis-config.json";
 
 	pub fn save(&self) {
-		let res = fs::write(
-			Self::PATH,
-			serde_json::to_string_pretty(self).expect("must ser"),
-		);
+		self.save_to(Path::new(Self::PATH));
+	}
+
+	pub fn save_to(&self, path: &Path) {
+		let res = fs::write(path, serde_json::to_string_pretty(self).expect("must ser"));
 
 		if let Err(_err) = res {
 			log::warn("Failed to save config file.".to_string());
@@ -100,9 +170,16 @@ impl TISConfig {
 			return default;
 		}
 
-		match fs::read(Self::PATH) {
-			Ok(v) => match serde_json::from_slice(&v) {
-				Ok(v) => v,
+		Self::load_from(Path::new(Self::PATH))
+	}
+
+	pub fn load_from(path: &Path) -> TISConfig {
+		match fs::read(path) {
+			Ok(v) => match serde_json::from_slice::<TISConfig>(&v) {
+				Ok(mut v) => {
+					v.migrate();
+					v
+				}
 				Err(err) => {
 					log::warn(format!("Failed to read config file. {err:?}"));
 
@@ -118,4 +195,79 @@ impl TISConfig {
 	}
 }
 
+impl TISConfig {
+	/// Turn the single server and token of an old config into a profile. Only changes the
+	/// config in memory, see [`TISConfig::finish_migration`] for the rest.
+	fn migrate(&mut self) {
+		let legacy = self.server.take();
+		let token = self.auth_token.take();
+
+		if !self.servers.is_empty() {
+			return;
+		}
+
+		let server = ServerConfig {
+			auth_token: token,
+			..legacy.unwrap_or_default()
+		};
+
+		self.active_server = server.name.clone();
+		self.migrated_to = Some(server.name.clone());
+		self.servers.push(server);
+	}
+
+	/// If the config at `path` was from before server profiles, hand the checkpoints and queue
+	/// from back then to the profile it became, and save the config so that only happens once.
+	/// Called on startup, right after loading.
+	pub fn finish_migration(&mut self, path: &Path) {
+		let Some(server) = self.migrated_to.take() else {
+			return;
+		};
+
+		Checkpoints::adopt_legacy(&server);
+		queue::adopt_legacy(&server);
+		self.save_to(path);
+
+		log::info(format!(
+			"Moved the old config over to the '{server}' profile."
+		));
+	}
+
+	/// The server imports go to by default. Falls back to the first one if the active server
+	/// was removed.
+	pub fn active_server(&self) -> Option<&ServerConfig> {
+		self.server_named(&self.active_server)
+			.or(self.servers.first())
+	}
+
+	pub fn active_server_mut(&mut self) -> Option<&mut ServerConfig> {
+		let name = self.active_server()?.name.clone();
+		self.servers.iter_mut().find(|e| e.name == name)
+	}
+
+	pub fn server_named(&self, name: &str) -> Option<&ServerConfig> {
+		self.servers.iter().find(|e| e.name == name)
+	}
+
+	/// `name` if given, otherwise the active server.
+	pub fn pick_server(&self, name: Option<&str>) -> anyhow::Result<&ServerConfig> {
+		match name {
+			Some(name) => self
+				.server_named(name)
+				.ok_or_else(|| anyhow!("There's no server called '{name}'.")),
+			None => self
+				.active_server()
+				.ok_or_else(|| anyhow!("There are no servers set up.")),
+		}
+	}
+
+	/// Add `server`, or replace the one with the same name.
+	pub fn upsert_server(&mut self, server: ServerConfig) {
+		match self.servers.iter_mut().find(|e| e.name == server.name) {
+			Some(existing) => *existing = server,
+			None => self.servers.push(server),
+		}
+	}
+}
+
 pub const SERVICE_NAME: &str = "TIS. v2.2.0";
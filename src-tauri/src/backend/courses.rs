@@ -0,0 +1,449 @@
+//! Dan courses, and working out which Tachi class clearing one is worth.
+//!
+//! Neither game's score database says which course a course score was for, only the charts
+//! in it. The dan courses TIS knows of are bundled from `courses.json`, grouped by the class
+//! set clearing them counts towards. More can be listed in `tis-courses.json`, in the same
+//! shape difficulty tables use for theirs plus a `class`. LR2's own `.lr2crs` course files can
+//! be read too, but they don't say what a course is worth.
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	fs,
+	path::Path,
+};
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+	batch_manual::BatchManualClasses,
+	bms::BMSGamemode,
+	log,
+	log::Logger,
+	report::{ConversionReport, SkipReason},
+	store::load_json,
+};
+
+const PATH: &str = "tis-courses.json";
+
+/// Class set -> the courses in it, each `{ "name", "class", "md5" or "sha256" }`. GENOCIDE's
+/// insane dans are `genocideDan`'s `INSANE_*` classes, and Satellite and Stella's are both
+/// `stslDan`, so there's no other set to list.
+const BUNDLED: &str = include_str!("../../courses.json");
+
+/// How deep into a course folder to look for `.lr2crs` files.
+const MAX_LR2CRS_DEPTH: usize = 4;
+
+/// Tachi's BMS class sets we know how to order, worst first.
+const GENOCIDE_DANS: &[&str] = &[
+	"NORMAL_1",
+	"NORMAL_2",
+	"NORMAL_3",
+	"NORMAL_4",
+	"NORMAL_5",
+	"NORMAL_6",
+	"NORMAL_7",
+	"NORMAL_8",
+	"NORMAL_9",
+	"NORMAL_10",
+	"INSANE_1",
+	"INSANE_2",
+	"INSANE_3",
+	"INSANE_4",
+	"INSANE_5",
+	"INSANE_6",
+	"INSANE_7",
+	"INSANE_8",
+	"INSANE_9",
+	"INSANE_10",
+	"INSANE_KAIDEN",
+	"OVERJOY",
+];
+
+const STSL_DANS: &[&str] = &[
+	"SL0", "SL1", "SL2", "SL3", "SL4", "SL5", "SL6", "SL7", "SL8", "SL9", "SL10", "SL11", "SL12",
+	"ST0", "ST1", "ST2", "ST3", "ST4", "ST5", "ST6", "ST7", "ST8", "ST9", "ST10", "ST11",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DanClass {
+	/// The class set, like `genocideDan`.
+	pub set: String,
+	pub class: String,
+}
+
+impl DanClass {
+	fn new(set: &str, class: impl Into<String>) -> Self {
+		Self {
+			set: set.into(),
+			class: class.into(),
+		}
+	}
+
+	/// Whether this is a class Tachi has.
+	pub fn is_known(&self) -> bool {
+		self.rank().is_some()
+	}
+
+	/// Where this sits in its set, if it's one we know the order of.
+	fn rank(&self) -> Option<usize> {
+		let order = match self.set.as_str() {
+			"genocideDan" => GENOCIDE_DANS,
+			"stslDan" => STSL_DANS,
+			_ => return None,
+		};
+
+		order.iter().position(|e| *e == self.class)
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Course {
+	pub name: String,
+	#[serde(default)]
+	pub md5: Vec<String>,
+	#[serde(default)]
+	pub sha256: Vec<String>,
+	/// The class clearing this course earns, if it's a dan.
+	#[serde(default)]
+	pub class: Option<DanClass>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BundledCourse {
+	name: String,
+	class: String,
+	#[serde(default)]
+	md5: Vec<String>,
+	#[serde(default)]
+	sha256: Vec<String>,
+}
+
+/// Every class in the sets we know, worst first within each.
+pub fn known_classes() -> Vec<DanClass> {
+	[("genocideDan", GENOCIDE_DANS), ("stslDan", STSL_DANS)]
+		.into_iter()
+		.flat_map(|(set, classes)| classes.iter().map(move |e| DanClass::new(set, *e)))
+		.collect()
+}
+
+/// The dan courses that come with TIS, each under the class set its list is for.
+pub fn bundled_courses() -> Vec<Course> {
+	let sets: BTreeMap<String, Vec<BundledCourse>> =
+		serde_json::from_str(BUNDLED).expect("bundled courses.json is invalid");
+
+	sets.into_iter()
+		.flat_map(|(set, courses)| {
+			courses.into_iter().map(move |e| Course {
+				name: e.name,
+				md5: e.md5,
+				sha256: e.sha256,
+				class: Some(DanClass::new(&set, e.class)),
+			})
+		})
+		.collect()
+}
+
+/// Every course listed in `tis-courses.json`, then the bundled ones. Table headers nest
+/// their courses in lists, so those are flattened out.
+pub fn load_courses() -> Vec<Course> {
+	fn flatten(value: Value, courses: &mut Vec<Course>) {
+		match value {
+			Value::Array(arr) => arr.into_iter().for_each(|e| flatten(e, courses)),
+			Value::Object(_) => match serde_json::from_value::<Course>(value) {
+				Ok(mut course) => {
+					if let Some(class) = course.class.take_if(|e| !e.is_known()) {
+						log::warn(format!(
+							"{} in {PATH} is for {} {}, which isn't a class Tachi has.",
+							course.name, class.set, class.class
+						));
+					}
+					courses.push(course);
+				}
+				Err(err) => log::warn(format!("Ignoring invalid course in {PATH}: {err}")),
+			},
+			_ => {}
+		}
+	}
+
+	let mut courses = vec![];
+	flatten(load_json::<Value>(PATH), &mut courses);
+	courses.extend(bundled_courses());
+	courses
+}
+
+/// Split an LR2 course hash into its charts' md5s. LR2 puts 32 zeroes in front of them, which
+/// are dropped if they're there.
+pub fn lr2_course_md5s(hash: &str) -> Option<Vec<&str>> {
+	if hash.len() % 32 != 0 || !hash.is_ascii() {
+		return None;
+	}
+
+	let mut md5s: Vec<_> = (0..hash.len())
+		.step_by(32)
+		.map(|i| &hash[i..i + 32])
+		.collect();
+
+	if md5s.first().is_some_and(|e| e.bytes().all(|b| b == b'0')) {
+		md5s.remove(0);
+	}
+
+	(md5s.len() > 1).then_some(md5s)
+}
+
+/// The text inside every `<tag>` in `xml`.
+fn tag_contents<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+	let open = format!("<{tag}>");
+	let close = format!("</{tag}>");
+
+	xml.split(&open)
+		.skip(1)
+		.filter_map(|e| e.split_once(&close).map(|(inner, _)| inner.trim()))
+		.collect()
+}
+
+fn unescape_xml(s: &str) -> String {
+	s.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&amp;", "&")
+}
+
+/// The courses in an `.lr2crs` file. LR2 writes them in Shift-JIS, but ones saved as UTF-8
+/// say so.
+pub fn parse_lr2crs(bytes: &[u8]) -> Vec<Course> {
+	let head = String::from_utf8_lossy(&bytes[..bytes.len().min(100)]).to_lowercase();
+	let encoding = if head.contains("utf-8") {
+		encoding_rs::UTF_8
+	} else {
+		encoding_rs::SHIFT_JIS
+	};
+	let (xml, _, _) = encoding.decode(bytes);
+
+	tag_contents(&xml, "course")
+		.into_iter()
+		.filter_map(|course| {
+			let name = unescape_xml(tag_contents(course, "title").first()?);
+			let md5 = lr2_course_md5s(tag_contents(course, "hash").first()?)?
+				.into_iter()
+				.map(str::to_owned)
+				.collect();
+
+			Some(Course {
+				name,
+				md5,
+				..Default::default()
+			})
+		})
+		.collect()
+}
+
+/// Every course in the `.lr2crs` file at `path`, or in every one under it if it's a folder.
+/// Symlinks inside the folder aren't followed, and it's only searched a few folders deep.
+pub fn load_lr2crs(path: &Path, log: &Logger) -> Vec<Course> {
+	load_lr2crs_in(path, 0, log)
+}
+
+fn load_lr2crs_in(path: &Path, depth: usize, log: &Logger) -> Vec<Course> {
+	if path.is_dir() {
+		let Ok(entries) = fs::read_dir(path) else {
+			log.warn(format!("Couldn't read course folder {}.", path.display()));
+			return vec![];
+		};
+
+		return entries
+			.flatten()
+			.flat_map(|e| {
+				let path = e.path();
+				let Ok(kind) = e.file_type() else {
+					return vec![];
+				};
+				let is_course = path
+					.extension()
+					.is_some_and(|e| e.eq_ignore_ascii_case("lr2crs"));
+
+				if kind.is_symlink() {
+					log.debug(format!("Not following symlink {}.", path.display()));
+					vec![]
+				} else if kind.is_dir() && depth < MAX_LR2CRS_DEPTH {
+					load_lr2crs_in(&path, depth + 1, log)
+				} else if kind.is_file() && is_course {
+					load_lr2crs_in(&path, depth, log)
+				} else {
+					vec![]
+				}
+			})
+			.collect();
+	}
+
+	match fs::read(path) {
+		Ok(bytes) => parse_lr2crs(&bytes),
+		Err(err) => {
+			log.warn(format!("Couldn't read {}: {err}", path.display()));
+			vec![]
+		}
+	}
+}
+
+/// The best dan cleared in each class set, per playtype.
+#[derive(Debug, Clone, Default)]
+pub struct Dans(BTreeMap<&'static str, BTreeMap<String, DanClass>>);
+
+impl Dans {
+	/// Record a clear, keeping whichever is higher if there's already one in the same set.
+	pub fn cleared(&mut self, playtype: &'static str, dan: DanClass) {
+		let best = self.0.entry(playtype).or_default();
+
+		match best.get(&dan.set) {
+			Some(old) if old.rank() >= dan.rank() => {}
+			_ => {
+				best.insert(dan.set.clone(), dan);
+			}
+		}
+	}
+
+	pub fn classes(&self, playtype: &str) -> BatchManualClasses {
+		BatchManualClasses(
+			self.0
+				.get(playtype)
+				.into_iter()
+				.flatten()
+				.map(|(set, dan)| (set.clone(), dan.class.clone()))
+				.collect(),
+		)
+	}
+}
+
+/// How a game's score database keys course scores: by its charts' hashes, strung together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CourseHashes {
+	/// beatoraja. Courses that only list md5s are looked up in the chart database.
+	Sha256,
+	/// LR2, whose chart database keys charts by md5 in its `hash` column.
+	Md5,
+}
+
+impl CourseHashes {
+	fn column(self) -> &'static str {
+		match self {
+			CourseHashes::Sha256 => "sha256",
+			CourseHashes::Md5 => "hash",
+		}
+	}
+
+	/// The hashes of `course`'s charts, if it lists them in a way that can be turned into
+	/// this format.
+	fn of(self, db: &Connection, course: &Course) -> anyhow::Result<Option<Vec<String>>> {
+		let hashes = match self {
+			CourseHashes::Sha256 if course.sha256.is_empty() => course
+				.md5
+				.iter()
+				.map(|md5| {
+					db.prepare_cached("SELECT sha256 FROM chart.song WHERE md5 = ?1 LIMIT 1")?
+						.query_row([md5], |row| row.get::<_, String>(0))
+						.optional()
+				})
+				.collect::<Result<Option<Vec<_>>, _>>()?,
+			CourseHashes::Sha256 => Some(course.sha256.clone()),
+			CourseHashes::Md5 => Some(course.md5.clone()),
+		};
+
+		Ok(hashes.filter(|e| !e.is_empty()))
+	}
+}
+
+/// The known courses a score database's course scores can be for, and the best dans cleared
+/// among them.
+pub struct CourseClears {
+	/// Courses keyed by their charts' hashes strung together, along with the playtype of
+	/// their charts.
+	lookup: HashMap<String, (Course, &'static str)>,
+	/// The lowest `clear` that counts for a dan.
+	min_clear: i32,
+	dans: Dans,
+}
+
+impl CourseClears {
+	/// Look up the charts of every course in `db`'s attached chart database. Courses listed
+	/// earlier win over later ones with the same charts.
+	pub fn new(
+		db: &Connection,
+		courses: impl IntoIterator<Item = Course>,
+		hashes: CourseHashes,
+		min_clear: i32,
+		log: &Logger,
+	) -> anyhow::Result<Self> {
+		let mut lookup = HashMap::new();
+
+		for course in courses {
+			let Some(charts) = hashes.of(db, &course)? else {
+				log.debug(format!(
+					"Not every chart in {} is in the chart database, ignoring it.",
+					course.name
+				));
+				continue;
+			};
+
+			let playtype = db
+				.prepare_cached(&format!(
+					"SELECT mode FROM chart.song WHERE {} = ?1 LIMIT 1",
+					hashes.column()
+				))?
+				.query_row([&charts[0]], |row| row.get::<_, i32>(0))
+				.optional()?
+				.and_then(BMSGamemode::from_keys)
+				.and_then(|e| e.playtype(None));
+
+			let Some(playtype) = playtype else {
+				log.debug(format!(
+					"Couldn't find the charts in {} in the chart database, ignoring it.",
+					course.name
+				));
+				continue;
+			};
+
+			lookup.entry(charts.concat()).or_insert((course, playtype));
+		}
+
+		Ok(Self {
+			lookup,
+			min_clear,
+			dans: Dans::default(),
+		})
+	}
+
+	/// Note down a course score. `charts` is its charts' hashes strung together, and `hash`
+	/// is what the score database has it under.
+	pub fn record(
+		&mut self,
+		hash: &str,
+		charts: &str,
+		clear: i32,
+		report: &mut ConversionReport,
+		log: &Logger,
+	) {
+		let Some((course, playtype)) = self.lookup.get(charts) else {
+			log.debug(format!("Skipping unknown course {hash}"));
+			report.skip(SkipReason::UnknownCourse, Some(hash), None);
+			return;
+		};
+
+		match &course.class {
+			Some(dan) if clear >= self.min_clear => {
+				log.info(format!(
+					"Found a clear of {}, sending {} {}.",
+					course.name, dan.set, dan.class
+				));
+				self.dans.cleared(playtype, dan.clone());
+			}
+			Some(_) => {}
+			None => log.debug(format!("{} isn't a dan we know of.", course.name)),
+		}
+	}
+
+	pub fn dans(&self) -> &Dans {
+		&self.dans
+	}
+}
@@ -0,0 +1,89 @@
+//! A log of every import TIS has finished, to check on later without going to the site.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::store::{load_json, save_json};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRecord {
+	#[serde(rename = "importID")]
+	pub import_id: String,
+	/// The import's page on the site.
+	pub url: String,
+	/// The name of the server it went to.
+	pub server: String,
+	/// Unix milliseconds.
+	pub time: i64,
+	pub source: Option<String>,
+	pub game: String,
+	pub playtype: String,
+	pub scores_sent: usize,
+	pub new_scores: usize,
+	pub failed: usize,
+	/// The copy of what was sent, in `batch-manual/`.
+	pub batch_manual: String,
+	#[serde(default)]
+	pub reverted: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryQuery {
+	pub source: Option<String>,
+	pub game: Option<String>,
+	pub playtype: Option<String>,
+	/// Only imports at or after this, in unix milliseconds.
+	pub since: Option<i64>,
+	pub limit: Option<usize>,
+}
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+const PATH: &str = "tis-history.json";
+
+pub fn record(record: ImportRecord) {
+	let _guard = LOCK.lock();
+	let mut history: Vec<ImportRecord> = load_json(PATH);
+	history.push(record);
+	save_json(PATH, &history);
+}
+
+/// Import IDs are only unique on one server, so `server` is needed to tell them apart.
+pub fn mark_reverted(server: &str, import_id: &str) {
+	let _guard = LOCK.lock();
+	let mut history: Vec<ImportRecord> = load_json(PATH);
+
+	for record in history
+		.iter_mut()
+		.filter(|e| e.server == server && e.import_id == import_id)
+	{
+		record.reverted = true;
+	}
+
+	save_json(PATH, &history);
+}
+
+/// Imports matching `query`, newest first.
+pub fn query(query: &HistoryQuery) -> Vec<ImportRecord> {
+	let history: Vec<ImportRecord> = {
+		let _guard = LOCK.lock();
+		load_json(PATH)
+	};
+
+	let matches =
+		|want: &Option<String>, have: Option<&str>| want.as_deref().is_none_or(|e| Some(e) == have);
+
+	history
+		.into_iter()
+		.rev()
+		.filter(|e| {
+			matches(&query.source, e.source.as_deref())
+				&& matches(&query.game, Some(&e.game))
+				&& matches(&query.playtype, Some(&e.playtype))
+				&& query.since.is_none_or(|since| e.time >= since)
+		})
+		.take(query.limit.unwrap_or(usize::MAX))
+		.collect()
+}
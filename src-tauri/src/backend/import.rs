@@ -0,0 +1,379 @@
+use std::{
+	fmt, fs,
+	time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use super::{
+	client::{Retry, TachiClient},
+	history::{self, ImportRecord},
+	jobs::{JobHandle, JobKind, JobStatus},
+	log,
+	validate::Problem,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportResponseBody {
+	url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportResponse {
+	success: bool,
+	description: String,
+	body: Option<ImportResponseBody>,
+}
+
+/// Any response we only need to know the outcome of.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiResponse {
+	success: bool,
+	description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportPollProgress {
+	description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "importStatus")]
+enum ImportPollResponseProgress {
+	#[serde(rename = "completed")]
+	Completed { import: ImportDocument },
+	#[serde(rename = "ongoing")]
+	Ongoing { progress: ImportPollProgress },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportPollResponseBody {
+	#[serde(flatten)]
+	progress: ImportPollResponseProgress,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportPollResponse {
+	success: bool,
+	description: String,
+	body: Option<ImportPollResponseBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportError {
+	#[serde(rename = "type")]
+	pub kind: String,
+	pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionChange {
+	Created,
+	Appended,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+	#[serde(rename = "sessionID")]
+	pub session_id: String,
+	#[serde(rename = "type")]
+	pub change: SessionChange,
+}
+
+/// A class (dan, genocide rank, etc.) that changed because of this import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassDelta {
+	pub set: String,
+	pub playtype: String,
+	pub old: Option<String>,
+	pub new: String,
+}
+
+/// The parts of Tachi's import document we care about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportDocument {
+	#[serde(rename = "importID")]
+	pub import_id: String,
+	#[serde(default)]
+	pub game: String,
+	#[serde(default)]
+	pub playtypes: Vec<String>,
+	#[serde(rename = "scoreIDs", default)]
+	pub score_ids: Vec<String>,
+	#[serde(default)]
+	pub errors: Vec<ImportError>,
+	#[serde(default)]
+	pub created_sessions: Vec<SessionInfo>,
+	#[serde(default)]
+	pub class_deltas: Vec<ClassDelta>,
+	pub time_started: Option<i64>,
+	pub time_finished: Option<i64>,
+	/// Scores we didn't send, as Tachi would have rejected them. Not from Tachi.
+	#[serde(default)]
+	pub left_out: Vec<Problem>,
+}
+
+impl fmt::Display for ImportDocument {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"Import {} ({} {})",
+			self.import_id,
+			self.game,
+			self.playtypes.join(", ")
+		)?;
+		writeln!(f, "New scores: {}", self.score_ids.len())?;
+
+		let created = self
+			.created_sessions
+			.iter()
+			.filter(|e| e.change == SessionChange::Created)
+			.count();
+		writeln!(
+			f,
+			"Sessions: {created} created, {} added to",
+			self.created_sessions.len() - created
+		)?;
+
+		if !self.class_deltas.is_empty() {
+			writeln!(f, "Class changes:")?;
+			for delta in &self.class_deltas {
+				writeln!(
+					f,
+					"  {} ({}): {} -> {}",
+					delta.set,
+					delta.playtype,
+					delta.old.as_deref().unwrap_or("none"),
+					delta.new
+				)?;
+			}
+		}
+
+		if !self.errors.is_empty() {
+			writeln!(f, "Errors ({}):", self.errors.len())?;
+			for err in &self.errors {
+				writeln!(f, "  {}: {}", err.kind, err.message)?;
+			}
+		}
+
+		if !self.left_out.is_empty() {
+			writeln!(f, "Left out ({}):", self.left_out.len())?;
+			for problem in &self.left_out {
+				writeln!(f, "  {problem}")?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn meta_field(bm: &serde_json::Value, key: &str) -> String {
+	bm.get("meta")
+		.and_then(|e| e.get(key))
+		.and_then(|e| e.as_str().map(|e| e.to_owned()))
+		.unwrap_or("invalid".to_owned())
+}
+
+/// Submit a batch manual and wait for Tachi to finish processing it.
+///
+/// A copy of the batch manual is always written to `batch-manual/` first, so that
+/// failed imports can be retried by hand. Once the import is done, a readable summary of it
+/// is written alongside.
+///
+/// Finished imports are added to the [`history`].
+///
+/// `user_intent` should be false for imports nobody explicitly asked for, like the ones
+/// made by watch mode.
+///
+/// Cancelling `job` stops us waiting on the import, but Tachi will still finish it if the
+/// batch manual has already been sent.
+pub async fn import_batch_manual(
+	client: &TachiClient,
+	bm: &serde_json::Value,
+	source: Option<&str>,
+	user_intent: bool,
+	job: &JobHandle,
+) -> anyhow::Result<ImportDocument> {
+	let deadline = client.deadline();
+
+	let _ = fs::create_dir_all("batch-manual");
+
+	log::info("Making import...".into());
+
+	let game = meta_field(bm, "game");
+	let playtype = meta_field(bm, "playtype");
+
+	let filename = format!("{}-{game}-{playtype}", Utc::now().timestamp_millis(),);
+
+	let _ = fs::write(
+		format!("batch-manual/{filename}.json"),
+		serde_json::to_string_pretty(bm).expect("must ser"),
+	);
+
+	job.check_cancelled()?;
+
+	let body = serde_json::to_string(bm).expect("must ser");
+
+	let res = client
+		.send(
+			|| {
+				client
+					.request(Method::POST, "/ir/direct-manual/import")
+					.header("Content-Type", "application/json")
+					.header("X-User-Intent", user_intent.to_string())
+					.body(body.clone())
+			},
+			Retry::NonIdempotent,
+			deadline,
+			job,
+		)
+		.await?;
+
+	if res.status().is_server_error() {
+		bail!(
+			"{} is having problems ({}).",
+			client.server().name,
+			res.status()
+		);
+	}
+
+	log::info("Request OK...".into());
+
+	let json_body: ImportResponse = res
+		.json()
+		.await
+		.map_err(|_| anyhow!("Invalid response from server."))?;
+
+	let Some(body) = json_body.body else {
+		bail!("Failed to submit scores. {}", json_body.description);
+	};
+
+	let mut last_desc = String::new();
+
+	let doc = loop {
+		job.check_cancelled()?;
+
+		if Instant::now() >= deadline {
+			return Err(client.deadline_error());
+		}
+
+		let res = client
+			.send(
+				|| client.request(Method::GET, &body.url),
+				Retry::Idempotent,
+				deadline,
+				job,
+			)
+			.await?;
+
+		let json: ImportPollResponse = res
+			.json()
+			.await
+			.map_err(|e| anyhow!("Invalid response from server ({e:?})."))?;
+
+		match json.body {
+			Some(body) => match body.progress {
+				ImportPollResponseProgress::Completed { import } => {
+					break import;
+				}
+				ImportPollResponseProgress::Ongoing { progress } => {
+					if progress.description != last_desc {
+						last_desc = progress.description;
+						log::info(last_desc.clone());
+						job.report(JobStatus::Importing {
+							description: last_desc.clone(),
+						});
+					}
+
+					job.until_cancelled(async {
+						tokio::time::sleep(Duration::from_secs(1)).await;
+						Ok(())
+					})
+					.await?;
+				}
+			},
+			None => {
+				log::error(format!("Failed to process import: {}", json.description));
+				bail!("Failed to process import: {}", json.description);
+			}
+		}
+	};
+	log::info(format!(
+		"Successfully imported scores for {game} ({playtype})"
+	));
+
+	log::info(format!(
+		"New Scores: {} | Failed {}",
+		doc.score_ids.len(),
+		doc.errors.len()
+	));
+
+	for delta in &doc.class_deltas {
+		log::info(format!(
+			"{} ({}) is now {}!",
+			delta.set, delta.playtype, delta.new
+		));
+	}
+
+	let _ = fs::write(
+		format!("batch-manual/{filename}.summary.txt"),
+		doc.to_string(),
+	);
+
+	history::record(ImportRecord {
+		import_id: doc.import_id.clone(),
+		url: format!("{}/imports/{}", client.server().client_url, doc.import_id),
+		server: client.server().name.clone(),
+		time: Utc::now().timestamp_millis(),
+		source: source.map(str::to_owned),
+		game,
+		playtype,
+		scores_sent: bm["scores"].as_array().map_or(0, |e| e.len()),
+		new_scores: doc.score_ids.len(),
+		failed: doc.errors.len(),
+		batch_manual: format!("batch-manual/{filename}.json"),
+		reverted: false,
+	});
+
+	Ok(doc)
+}
+
+/// Ask Tachi to undo an import, removing every score and session it made.
+pub async fn revert_import(client: &TachiClient, import_id: &str) -> anyhow::Result<()> {
+	log::info(format!("Reverting import {import_id}..."));
+
+	let res = client
+		.send(
+			|| client.request(Method::POST, &format!("/api/v1/imports/{import_id}/revert")),
+			// Reverting twice fails the second time, so don't risk it.
+			Retry::NonIdempotent,
+			client.deadline(),
+			&JobHandle::detached(JobKind::Import),
+		)
+		.await?;
+
+	let status = res.status();
+
+	if status.is_server_error() {
+		bail!("{} is having problems ({status}).", client.server().name);
+	}
+
+	let json: ApiResponse = res
+		.json()
+		.await
+		.map_err(|_| anyhow!("Invalid response from server."))?;
+
+	if !json.success {
+		bail!("Failed to revert import {import_id}. {}", json.description);
+	}
+
+	history::mark_reverted(&client.server().name, import_id);
+	log::info(format!("Reverted import {import_id}."));
+
+	Ok(())
+}
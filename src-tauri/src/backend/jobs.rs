@@ -0,0 +1,216 @@
+//! Long running work (conversions and imports) that the frontend can watch and cancel.
+
+use std::{
+	collections::HashMap,
+	fmt,
+	future::Future,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Arc,
+	},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+use tokio::sync::Notify;
+
+use super::log::TauriSink;
+
+pub type JobId = u64;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobKind {
+	Convert,
+	Import,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+	tag = "status",
+	rename_all = "camelCase",
+	rename_all_fields = "camelCase"
+)]
+pub enum JobStatus {
+	Started,
+	Converting {
+		rows_scanned: usize,
+		scores_kept: usize,
+	},
+	/// Tachi's own description of how the import is going.
+	Importing {
+		description: String,
+	},
+	Finished,
+	Failed {
+		error: String,
+	},
+	Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobEvent {
+	pub id: JobId,
+	pub kind: JobKind,
+	#[serde(flatten)]
+	pub status: JobStatus,
+}
+
+/// Somewhere for job events to end up, like [`super::log::LogSink`] is for logs.
+pub trait JobSink: Send + Sync {
+	fn emit(&self, event: &JobEvent);
+}
+
+/// Sends events to the frontend as `job` events.
+impl JobSink for TauriSink {
+	fn emit(&self, event: &JobEvent) {
+		let _ = self.0.emit("job", event);
+	}
+}
+
+/// For jobs nobody is watching.
+impl JobSink for () {
+	fn emit(&self, _event: &JobEvent) {}
+}
+
+impl<S: JobSink + ?Sized> JobSink for Arc<S> {
+	fn emit(&self, event: &JobEvent) {
+		(**self).emit(event);
+	}
+}
+
+/// Returned by a job that stopped because it was cancelled.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Cancelled.")
+	}
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Set once a job is asked to stop. Waiters are woken, for work that's stuck awaiting
+/// something.
+#[derive(Default)]
+struct Cancellation {
+	requested: AtomicBool,
+	notify: Notify,
+}
+
+/// Given to the code doing a job's work, to report progress and notice cancellation.
+#[derive(Clone)]
+pub struct JobHandle {
+	id: JobId,
+	kind: JobKind,
+	cancelled: Arc<Cancellation>,
+	sink: Arc<dyn JobSink>,
+}
+
+impl JobHandle {
+	/// A job that isn't registered anywhere, so can't be cancelled and reports to nobody.
+	pub fn detached(kind: JobKind) -> Self {
+		Self {
+			id: 0,
+			kind,
+			cancelled: Arc::default(),
+			sink: Arc::new(()),
+		}
+	}
+
+	pub fn id(&self) -> JobId {
+		self.id
+	}
+
+	pub fn report(&self, status: JobStatus) {
+		self.sink.emit(&JobEvent {
+			id: self.id,
+			kind: self.kind,
+			status,
+		});
+	}
+
+	/// Bail with [`Cancelled`] if someone has asked this job to stop.
+	pub fn check_cancelled(&self) -> anyhow::Result<()> {
+		if self.cancelled.requested.load(Ordering::Relaxed) {
+			return Err(Cancelled.into());
+		}
+
+		Ok(())
+	}
+
+	/// Run `fut`, but give up on it with [`Cancelled`] as soon as someone asks this job to
+	/// stop, rather than waiting for it to finish.
+	pub async fn until_cancelled<T>(
+		&self,
+		fut: impl Future<Output = anyhow::Result<T>>,
+	) -> anyhow::Result<T> {
+		// Registered before checking the flag, so a cancel in between isn't missed.
+		let notified = self.cancelled.notify.notified();
+		self.check_cancelled()?;
+
+		tokio::select! {
+			res = fut => res,
+			_ = notified => Err(Cancelled.into()),
+		}
+	}
+}
+
+/// Every job currently running.
+pub struct Jobs {
+	next_id: AtomicU64,
+	running: Mutex<HashMap<JobId, Arc<Cancellation>>>,
+	sink: Arc<dyn JobSink>,
+}
+
+impl Jobs {
+	pub fn new(sink: impl JobSink + 'static) -> Self {
+		Self {
+			next_id: AtomicU64::new(1),
+			running: Mutex::default(),
+			sink: Arc::new(sink),
+		}
+	}
+
+	pub fn start(&self, kind: JobKind) -> JobHandle {
+		let job = JobHandle {
+			id: self.next_id.fetch_add(1, Ordering::Relaxed),
+			kind,
+			cancelled: Arc::default(),
+			sink: self.sink.clone(),
+		};
+
+		self.running.lock().insert(job.id, job.cancelled.clone());
+		job.report(JobStatus::Started);
+
+		job
+	}
+
+	/// Report how `job` ended and forget about it.
+	pub fn finish<T>(&self, job: &JobHandle, result: &anyhow::Result<T>) {
+		self.running.lock().remove(&job.id);
+
+		job.report(match result {
+			Ok(_) => JobStatus::Finished,
+			Err(err) if err.is::<Cancelled>() => JobStatus::Cancelled,
+			Err(err) => JobStatus::Failed {
+				error: err.to_string(),
+			},
+		});
+	}
+
+	/// Ask a job to stop. Returns false if there's no such job running.
+	pub fn cancel(&self, id: JobId) -> bool {
+		match self.running.lock().get(&id) {
+			Some(cancelled) => {
+				cancelled.requested.store(true, Ordering::Relaxed);
+				cancelled.notify.notify_waiters();
+				true
+			}
+			None => false,
+		}
+	}
+}
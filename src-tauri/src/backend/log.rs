@@ -1,11 +1,18 @@
+use std::{
+	fmt,
+	fs::{File, OpenOptions},
+	io::{self, Write},
+	path::Path,
+	sync::Arc,
+};
+
 use chrono::Utc;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use tauri::Emitter;
+use tauri::{AppHandle, Emitter};
 use tracing::Level;
 
-use crate::APP_HANDLE;
-
-#[derive(Debug, Clone, Serialize, Deserialize, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "camelCase")]
 pub enum SerializableLevel {
 	Debug,
@@ -15,69 +22,179 @@ pub enum SerializableLevel {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct LogEvent {
+pub struct LogEvent {
 	#[serde(with = "chrono::serde::ts_milliseconds")]
-	timestamp: chrono::DateTime<chrono::Utc>,
-	level: SerializableLevel,
-	msg: String,
+	pub timestamp: chrono::DateTime<chrono::Utc>,
+	pub level: SerializableLevel,
+	pub msg: String,
 }
 
-#[inline(always)]
-#[track_caller]
-pub fn debug(msg: String) {
-	tracing::event!(Level::DEBUG, msg);
+impl fmt::Display for LogEvent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let level = match self.level {
+			SerializableLevel::Debug => "DEBUG",
+			SerializableLevel::Info => "INFO",
+			SerializableLevel::Warn => "WARN",
+			SerializableLevel::Error => "ERROR",
+		};
 
-	let _ = APP_HANDLE.get().unwrap().emit(
-		"log",
-		LogEvent {
-			timestamp: Utc::now(),
-			level: SerializableLevel::Debug,
-			msg,
-		},
-	);
+		write!(
+			f,
+			"[{}] {level}: {}",
+			self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+			self.msg
+		)
+	}
 }
 
-#[inline(always)]
-#[track_caller]
-pub fn warn(msg: String) {
-	tracing::event!(Level::WARN, msg);
+/// Somewhere for log events to end up. Every event is also sent to `tracing`, regardless
+/// of sink.
+pub trait LogSink: Send + Sync {
+	fn emit(&self, event: &LogEvent);
+}
 
-	let _ = APP_HANDLE.get().unwrap().emit(
-		"log",
-		LogEvent {
-			timestamp: Utc::now(),
-			level: SerializableLevel::Warn,
-			msg,
-		},
-	);
+/// Sends events to the frontend console as `log` events.
+pub struct TauriSink(pub AppHandle);
+
+impl LogSink for TauriSink {
+	fn emit(&self, event: &LogEvent) {
+		let _ = self.0.emit("log", event);
+	}
 }
 
-#[inline(always)]
-#[track_caller]
-pub fn info(msg: String) {
-	tracing::event!(Level::INFO, msg);
+pub struct StderrSink {
+	pub min_level: SerializableLevel,
+}
 
-	let _ = APP_HANDLE.get().unwrap().emit(
-		"log",
-		LogEvent {
-			timestamp: Utc::now(),
-			level: SerializableLevel::Info,
-			msg,
-		},
-	);
+impl LogSink for StderrSink {
+	fn emit(&self, event: &LogEvent) {
+		if event.level >= self.min_level {
+			eprintln!("{event}");
+		}
+	}
 }
 
-#[inline(always)]
-#[track_caller]
-pub fn error(msg: String) {
-	tracing::event!(Level::ERROR, msg);
+/// Keeps every event around. Mostly useful for tests.
+#[derive(Default)]
+pub struct MemorySink(Mutex<Vec<LogEvent>>);
+
+impl MemorySink {
+	pub fn events(&self) -> Vec<LogEvent> {
+		self.0.lock().clone()
+	}
+}
+
+impl LogSink for MemorySink {
+	fn emit(&self, event: &LogEvent) {
+		self.0.lock().push(event.clone());
+	}
+}
+
+/// Appends events to a file, one per line.
+pub struct FileSink {
+	file: Mutex<File>,
+	min_level: SerializableLevel,
+}
 
-	let _ = APP_HANDLE.get().unwrap().emit(
-		"log",
-		LogEvent {
+impl FileSink {
+	pub fn open(path: &Path, min_level: SerializableLevel) -> io::Result<Self> {
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+		Ok(Self {
+			file: Mutex::new(file),
+			min_level,
+		})
+	}
+}
+
+impl LogSink for FileSink {
+	fn emit(&self, event: &LogEvent) {
+		if event.level >= self.min_level {
+			let _ = writeln!(self.file.lock(), "{event}");
+		}
+	}
+}
+
+// Lets an `Arc<MemorySink>` be handed to a logger while the caller keeps a handle to it.
+impl<S: LogSink + ?Sized> LogSink for Arc<S> {
+	fn emit(&self, event: &LogEvent) {
+		(**self).emit(event);
+	}
+}
+
+#[derive(Clone)]
+pub struct Logger {
+	sink: Arc<dyn LogSink>,
+}
+
+impl Logger {
+	pub fn new(sink: impl LogSink + 'static) -> Self {
+		Self {
+			sink: Arc::new(sink),
+		}
+	}
+
+	fn log(&self, level: SerializableLevel, msg: String) {
+		match level {
+			SerializableLevel::Debug => tracing::event!(Level::DEBUG, msg),
+			SerializableLevel::Info => tracing::event!(Level::INFO, msg),
+			SerializableLevel::Warn => tracing::event!(Level::WARN, msg),
+			SerializableLevel::Error => tracing::event!(Level::ERROR, msg),
+		}
+
+		self.sink.emit(&LogEvent {
 			timestamp: Utc::now(),
-			level: SerializableLevel::Error,
+			level,
 			msg,
-		},
-	);
+		});
+	}
+
+	pub fn debug(&self, msg: String) {
+		self.log(SerializableLevel::Debug, msg);
+	}
+
+	pub fn info(&self, msg: String) {
+		self.log(SerializableLevel::Info, msg);
+	}
+
+	pub fn warn(&self, msg: String) {
+		self.log(SerializableLevel::Warn, msg);
+	}
+
+	pub fn error(&self, msg: String) {
+		self.log(SerializableLevel::Error, msg);
+	}
+}
+
+static GLOBAL: RwLock<Option<Logger>> = parking_lot::const_rwlock(None);
+
+/// Set the logger used by the free functions in this module.
+pub fn set_global(logger: Logger) {
+	*GLOBAL.write() = Some(logger);
+}
+
+/// The process-wide logger, for code that isn't handed one. Falls back to stderr if
+/// nothing has been set up yet.
+pub fn global() -> Logger {
+	GLOBAL.read().clone().unwrap_or_else(|| {
+		Logger::new(StderrSink {
+			min_level: SerializableLevel::Info,
+		})
+	})
+}
+
+pub fn debug(msg: String) {
+	global().debug(msg);
+}
+
+pub fn warn(msg: String) {
+	global().warn(msg);
+}
+
+pub fn info(msg: String) {
+	global().info(msg);
+}
+
+pub fn error(msg: String) {
+	global().error(msg);
 }
@@ -1,5 +1,4 @@
-use anyhow::bail;
-use rusqlite::OptionalExtension;
+use rusqlite::Connection;
 
 use crate::backend::{
 	batch_manual::{BMSBatchManualScore, BMSClient, BMSLamp, BMSOptionalMetrics, BMSScoreMeta},
@@ -7,11 +6,13 @@ use crate::backend::{
 };
 
 use super::{
-	batch_manual::{BMSBatchManual, BMSJudgements, BatchManualClasses, BatchManualMeta},
+	batch_manual::BMSJudgements,
 	bms::{BMSConvertResults, BMSGamemode},
-	config::{LR2Config, SERVICE_NAME},
-	log,
-	sqlite::connect_sqlite3,
+	config::{LR2Config, TISConfig},
+	courses::{load_lr2crs, lr2_course_md5s, CourseClears, CourseHashes},
+	report::{ConversionReport, Converted, SkipReason},
+	source::{ConvertContext, ScoreSource},
+	sqlite::table_has_columns,
 };
 
 #[derive(Debug, Clone)]
@@ -34,19 +35,96 @@ pub struct ChartRow {
 	mode: Option<BMSGamemode>,
 }
 
+/// Course clears only count for dans on the normal gauge or better.
+const COURSE_CLEAR: i32 = 3;
+
+pub struct LR2;
+
+impl ScoreSource for LR2 {
+	const ID: &'static str = "lr2";
+
+	type Config = LR2Config;
+	type Output = BMSConvertResults;
+
+	fn detect(db: &Connection) -> bool {
+		table_has_columns(db, "score", &["hash", "op_best", "complete"])
+	}
+
+	fn convert(
+		config: &LR2Config,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<Converted<BMSConvertResults>> {
+		convert_lr2_db(config, ctx)
+	}
+
+	fn saved_config(config: &mut TISConfig) -> &mut Option<LR2Config> {
+		&mut config.lr2
+	}
+}
+
 pub fn convert_lr2_db(
 	LR2Config {
 		chart_path,
 		score_path,
+		pms_playtype,
+		course_path,
 	}: &LR2Config,
-) -> anyhow::Result<BMSConvertResults> {
-	let score_db = connect_sqlite3(score_path)?;
-	let chart_db = connect_sqlite3(chart_path)?;
+	ctx: &ConvertContext,
+) -> anyhow::Result<Converted<BMSConvertResults>> {
+	let log = &ctx.log;
+	let mut report = ConversionReport {
+		chart_db: chart_path
+			.file_name()
+			.map(|e| e.to_string_lossy().into_owned()),
+		..Default::default()
+	};
+
+	let db = ctx.open_with_charts(score_path, chart_path)?;
 
-	let mut db_scores = score_db.prepare("SELECT * FROM score WHERE complete = 1")?;
+	// Courses listed earlier say what they're worth, .lr2crs files don't.
+	let lr2crs = course_path
+		.as_deref()
+		.map(|e| load_lr2crs(e, log))
+		.unwrap_or_default();
+	let mut courses = CourseClears::new(
+		&db,
+		ctx.courses.iter().cloned().chain(lr2crs),
+		CourseHashes::Md5,
+		COURSE_CLEAR,
+		log,
+	)?;
+
+	// song.db can list the same chart more than once (once per folder it's in), so collapse
+	// it down to one row per hash before joining.
+	let mut db_scores = db.prepare(
+		"
+		SELECT
+			score.*,
+			song.hash AS chart_hash,
+			song.title AS chart_title,
+			song.subtitle AS chart_subtitle,
+			song.mode AS chart_mode
+		FROM
+			score
+			LEFT JOIN (
+				SELECT hash, title, subtitle, mode FROM chart.song GROUP BY hash
+			) AS song ON song.hash = score.hash
+		WHERE
+			score.complete = 1
+	",
+	)?;
 
 	let scores = db_scores.query_map([], |row| {
-		Ok(ScoreRow {
+		let chart = match row.get::<_, Option<String>>("chart_hash")? {
+			Some(_) => Some(ChartRow {
+				title: row.get("chart_title")?,
+				subtitle: row.get("chart_subtitle")?,
+				mode: BMSGamemode::from_keys(row.get("chart_mode")?),
+			}),
+			None => None,
+		};
+
+		let score = ScoreRow {
 			hash: row.get("hash")?,
 			clear: row.get("clear")?,
 			perfect: row.get("perfect")?,
@@ -57,94 +135,104 @@ pub fn convert_lr2_db(
 			maxcombo: row.get("maxcombo")?,
 			minbp: row.get("minbp")?,
 			op_best: row.get("op_best")?,
-		})
+		};
+
+		Ok((score, chart))
 	})?;
 
 	let mut scores_7k = vec![];
 	let mut scores_14k = vec![];
+	let mut scores_pms = vec![];
 
-	for score in scores {
-		let score = match score {
+	for (i, row) in scores.enumerate() {
+		ctx.row_scanned(i + 1, scores_7k.len() + scores_14k.len() + scores_pms.len())?;
+
+		let (score, chart) = match row {
 			Ok(v) => v,
 			Err(err) => {
-				log::warn(format!("Invalid score in DB: {err}. Skipping."));
+				log.debug(format!("Invalid score in DB: {err}. Skipping."));
+				report.skip(SkipReason::InvalidRow, None, None);
 				continue;
 			}
 		};
 
-		let mut chart_query =
-			chart_db.prepare("SELECT title, subtitle, mode FROM song WHERE hash = ?1")?;
-
-		let chart = chart_query
-			.query_row([&score.hash], |row| {
-				Ok(ChartRow {
-					title: row.get("title")?,
-					subtitle: row.get("subtitle")?,
-					mode: match row.get::<_, i32>("mode")? {
-						7 => Some(BMSGamemode::SevenKey),
-						14 => Some(BMSGamemode::FourteenKey),
-						_ => None,
-					},
-				})
-			})
-			.optional()?;
+		if let Some(md5s) = lr2_course_md5s(&score.hash) {
+			courses.record(&score.hash, &md5s.concat(), score.clear, &mut report, log);
+			continue;
+		}
 
 		let Some(chart) = chart else {
-			log::warn(format!(
+			log.debug(format!(
 				"Couldn't find a matching chart for score {}",
 				score.hash
 			));
+			report.skip(SkipReason::ChartNotFound, Some(&score.hash), None);
 			continue;
 		};
 
 		let name = format!("{} {}", chart.title, chart.subtitle.unwrap_or_default());
 
 		let Some(mode) = chart.mode else {
-			log::debug(format!("Skipping unknown gamemode for {name}"));
+			log.debug(format!("Skipping unknown gamemode for {name}"));
+			report.skip(SkipReason::UnknownGamemode, Some(&score.hash), Some(&name));
 			continue;
 		};
 
+		if mode.playtype(*pms_playtype).is_none() {
+			log.debug(format!(
+				"Skipping PMS score on {name}, as no PMS playtype was picked."
+			));
+			report.skip(SkipReason::NoPmsPlaytype, Some(&score.hash), Some(&name));
+			continue;
+		}
+
 		let mut random = None;
 
-		match mode {
-			BMSGamemode::SevenKey => {
-				random = Some(match parse_random(score.op_best) {
-					Some(v) => v,
-					None => {
-						bail!("Invalid random option.")
-					}
-				});
-			}
-			BMSGamemode::FourteenKey => {}
+		if mode.has_random() {
+			random = Some(match parse_random(score.op_best) {
+				Some(v) => v,
+				None => {
+					log.debug(format!(
+						"Skipping score on {name} as it has an unknown play option {}.",
+						score.op_best
+					));
+					report.skip(SkipReason::InvalidRandom, Some(&score.hash), Some(&name));
+					continue;
+				}
+			});
 		}
 
 		if score.minbp < 0 {
-			log::info(format!(
+			log.debug(format!(
 				"Skipping score on {name} as it had a bp of {}. Probably autoscratch?",
 				score.minbp
 			));
+			report.skip(SkipReason::AutoScratch, Some(&score.hash), Some(&name));
 			continue;
 		}
 
+		let lamp = match score.clear {
+			0 => BMSLamp::NoPlay,
+			1 => BMSLamp::Failed,
+			2 => BMSLamp::EasyClear,
+			3 => BMSLamp::Clear,
+			4 => BMSLamp::HardClear,
+			5 => BMSLamp::FullCombo,
+			invalid => {
+				log.debug(format!(
+					"Invalid lamp on {name} -- got {invalid}; ignoring."
+				));
+				report.skip(SkipReason::InvalidLamp, Some(&score.hash), Some(&name));
+				continue;
+			}
+		};
+
 		let score = BMSBatchManualScore {
 			comment: None,
 			identifier: score.hash,
 			match_type: "bmsChartHash".into(),
 			score: (score.perfect * 2 + score.great) as u64,
-			lamp: match score.clear {
-				0 => BMSLamp::NoPlay,
-				1 => BMSLamp::Failed,
-				2 => BMSLamp::EasyClear,
-				3 => BMSLamp::Clear,
-				4 => BMSLamp::HardClear,
-				5 => BMSLamp::FullCombo,
-				invalid => {
-					log::warn(format!(
-						"Invalid lamp on {name} -- got {invalid}; ignoring."
-					));
-					continue;
-				}
-			},
+			lamp,
 			time_achieved: None,
 			optional: Some(BMSOptionalMetrics {
 				bp: Some(score.minbp),
@@ -186,50 +274,29 @@ pub fn convert_lr2_db(
 			BMSGamemode::FourteenKey => {
 				scores_14k.push(score);
 			}
+			BMSGamemode::NineKey => {
+				scores_pms.push(score);
+			}
 		}
 	}
 
-	let mut ret = BMSConvertResults {
-		k14: None,
-		k7: None,
-	};
-
-	if !scores_7k.is_empty() {
-		ret.k7 = Some(BMSBatchManual {
-			classes: BatchManualClasses::default(),
-			meta: BatchManualMeta {
-				game: "bms".into(),
-				playtype: "7K".into(),
-				service: SERVICE_NAME.into(),
-				version: None,
-			},
-			scores: scores_7k,
-		});
-	}
-
-	if !scores_14k.is_empty() {
-		ret.k14 = Some(BMSBatchManual {
-			classes: BatchManualClasses::default(),
-			meta: BatchManualMeta {
-				game: "bms".into(),
-				playtype: "14K".into(),
-				service: SERVICE_NAME.into(),
-				version: None,
-			},
-			scores: scores_14k,
-		});
-	}
-
-	if ret.k14.is_none() && ret.k7.is_none() {
-		log::warn("Converted no scores! Nothing will be uploaded.".into());
-	}
+	report.finish(scores_7k.len() + scores_14k.len() + scores_pms.len(), log);
 
-	Ok(ret)
+	Ok(Converted {
+		output: BMSConvertResults::from_scores(
+			scores_7k,
+			scores_14k,
+			scores_pms,
+			*pms_playtype,
+			courses.dans(),
+			log,
+		),
+		report,
+	})
 }
 
 fn parse_random(rand: i32) -> Option<BMSRandom> {
 	if rand > 100 {
-		log::warn(format!("unknown play option {rand}. Skipping."));
 		return None;
 	}
 
@@ -240,9 +307,6 @@ fn parse_random(rand: i32) -> Option<BMSRandom> {
 		1 => BMSRandom::Mirror,
 		2 => BMSRandom::Random,
 		3 => BMSRandom::SRandom,
-		unknown => {
-			log::warn(format!("unknown play option {unknown}. Skipping."));
-			return None;
-		}
+		_ => return None,
 	})
 }
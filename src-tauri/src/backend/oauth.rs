@@ -0,0 +1,215 @@
+//! Logging in through the browser with Tachi's OAuth2 authorization code flow, instead of
+//! pasting a token in.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::{
+	io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+	net::{TcpListener, TcpStream},
+};
+
+use super::{config::ServerConfig, log};
+
+/// How long to wait for someone to click through the authorize page.
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+struct TokenRequest<'a> {
+	client_id: &'a str,
+	client_secret: &'a str,
+	grant_type: &'a str,
+	redirect_uri: &'a str,
+	code: &'a str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TokenResponseBody {
+	token: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TokenResponse {
+	success: bool,
+	description: String,
+	body: Option<TokenResponseBody>,
+}
+
+/// Unguessable, so another page on this machine can't forge a redirect.
+fn random_state() -> anyhow::Result<String> {
+	let mut bytes = [0; 16];
+	getrandom::getrandom(&mut bytes)
+		.map_err(|e| anyhow!("Couldn't start logging in, no randomness available: {e}"))?;
+
+	Ok(format!("{:032x}", u128::from_le_bytes(bytes)))
+}
+
+/// Where to send the user to let TIS use their account.
+pub fn authorize_url(server: &ServerConfig, redirect_uri: &str, state: &str) -> String {
+	Url::parse_with_params(
+		&format!("{}/oauth/request-auth", server.client_url),
+		[
+			("clientID", server.client_id.as_str()),
+			("redirect_uri", redirect_uri),
+			("context", state),
+		],
+	)
+	.map(String::from)
+	.unwrap_or_default()
+}
+
+/// Wait for the browser to come back to the loopback listener, and pull the code out of it.
+/// Anything else that turns up, like favicon requests, redirects from an older attempt or
+/// requests that can't be read, is ignored.
+async fn wait_for_code(listener: &TcpListener, state: &str) -> anyhow::Result<String> {
+	loop {
+		let (stream, _) = listener.accept().await?;
+
+		if let Some(result) = handle_redirect(stream, state).await {
+			return result;
+		}
+	}
+}
+
+/// How the login went, or `None` if this wasn't the redirect.
+async fn handle_redirect(mut stream: TcpStream, state: &str) -> Option<anyhow::Result<String>> {
+	let mut line = String::new();
+	if let Err(err) = BufReader::new(&mut stream).read_line(&mut line).await {
+		log::warn(format!(
+			"Couldn't read a request to the login listener: {err}"
+		));
+		return None;
+	}
+
+	let path = line.split_whitespace().nth(1).unwrap_or("/");
+	let url = match Url::parse(&format!("http://localhost{path}")) {
+		Ok(url) => url,
+		Err(err) => {
+			log::warn(format!(
+				"Ignoring a request to the login listener for {path}: {err}"
+			));
+			respond(&mut stream, "400 Bad Request", "Bad request.").await;
+			return None;
+		}
+	};
+
+	if url.path() != "/callback" {
+		respond(&mut stream, "404 Not Found", "Not found.").await;
+		return None;
+	}
+
+	let param = |name: &str| {
+		url.query_pairs()
+			.find(|(k, _)| k == name)
+			.map(|(_, v)| v.into_owned())
+	};
+
+	if param("context").as_deref() != Some(state) {
+		respond(
+			&mut stream,
+			"400 Bad Request",
+			"This isn't from the latest login attempt.",
+		)
+		.await;
+		return None;
+	}
+
+	let result = if let Some(code) = param("code") {
+		Ok(code)
+	} else {
+		Err(anyhow!(
+			"Login was cancelled or refused. {}",
+			param("error").unwrap_or_default()
+		))
+	};
+
+	let body = match &result {
+		Ok(_) => "Logged in! You can close this tab and go back to TIS.".to_owned(),
+		Err(err) => err.to_string(),
+	};
+	respond(&mut stream, "200 OK", &body).await;
+
+	Some(result)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+	let res = format!(
+		"HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+		body.len()
+	);
+
+	let _ = stream.write_all(res.as_bytes()).await;
+}
+
+/// Trade an authorization code for an API token.
+async fn exchange_code(
+	server: &ServerConfig,
+	client_secret: &str,
+	redirect_uri: &str,
+	code: &str,
+) -> anyhow::Result<String> {
+	let res = reqwest::Client::builder()
+		.timeout(Duration::from_secs(60))
+		.build()?
+		.post(format!("{}/api/v1/oauth/token", server.base_url))
+		.json(&TokenRequest {
+			client_id: &server.client_id,
+			client_secret,
+			grant_type: "authorization_code",
+			redirect_uri,
+			code,
+		})
+		.send()
+		.await
+		.map_err(|e| anyhow!("Couldn't reach {}: {e}", server.name))?;
+
+	let json: TokenResponse = res
+		.json()
+		.await
+		.map_err(|_| anyhow!("Invalid response from server."))?;
+
+	match json.body {
+		Some(body) if json.success => Ok(body.token),
+		_ => bail!("Failed to get a token. {}", json.description),
+	}
+}
+
+/// Log in to `server` through the browser and return the new API token.
+///
+/// `open` is given the authorize URL to show the user. The redirect is caught on
+/// `127.0.0.1`, on the server's `redirect_port`.
+pub async fn login(
+	server: &ServerConfig,
+	open: impl FnOnce(&str) -> anyhow::Result<()>,
+	timeout: Duration,
+) -> anyhow::Result<String> {
+	let name = &server.name;
+
+	let Some(client_secret) = server.client_secret.as_deref().filter(|e| !e.is_empty()) else {
+		bail!("{name} has no client_secret, so it can't be logged into through the browser. Paste a token in instead.");
+	};
+	let Some(port) = server.redirect_port.filter(|&e| e != 0) else {
+		bail!("{name} has no redirect_port. Set it to the port in the redirect URI its client was registered with.");
+	};
+
+	let listener = TcpListener::bind(("127.0.0.1", port))
+		.await
+		.map_err(|e| anyhow!("Couldn't listen for the login redirect on port {port}: {e}"))?;
+
+	let redirect_uri = format!("http://127.0.0.1:{port}/callback");
+	let state = random_state()?;
+
+	log::info(format!("Opening {name} in your browser to log in..."));
+	open(&authorize_url(server, &redirect_uri, &state))?;
+
+	let code = tokio::time::timeout(timeout, wait_for_code(&listener, &state))
+		.await
+		.map_err(|_| anyhow!("Gave up waiting for the login to finish."))??;
+
+	let token = exchange_code(server, client_secret, &redirect_uri, &code).await?;
+	log::info(format!("Logged in to {name}."));
+
+	Ok(token)
+}
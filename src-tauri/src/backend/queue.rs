@@ -0,0 +1,317 @@
+//! Batch manuals waiting to be uploaded, kept on disk so that imports made while offline
+//! aren't forgotten.
+
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+	auth::check_can_import,
+	checkpoint::Checkpoints,
+	client::{is_offline, TachiClient},
+	import::ImportDocument,
+	jobs::{JobHandle, JobKind},
+	log,
+	store::{load_json, save_json},
+	upload::import_in_chunks,
+};
+
+pub type QueueItemId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QueueState {
+	/// Not uploaded yet, or the server couldn't be reached last time.
+	Pending,
+	Uploading,
+	Done,
+	/// The server turned it down. Only retried when asked to.
+	Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueItem {
+	pub id: QueueItemId,
+	/// The source this came from, to move its checkpoint along once uploaded.
+	pub source: Option<String>,
+	/// Name of the server profile it's going to. Empty for imports queued before there were
+	/// profiles, until the config is migrated.
+	#[serde(default)]
+	pub server: String,
+	pub game: String,
+	pub playtype: String,
+	pub scores: usize,
+	/// Where the batch manual is kept until it's uploaded.
+	pub path: PathBuf,
+	/// Unix milliseconds.
+	pub added: i64,
+	pub state: QueueState,
+	pub attempts: u32,
+	pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Queue {
+	next_id: QueueItemId,
+	items: Vec<QueueItem>,
+}
+
+/// Imports run concurrently, so every change to the queue file goes through here.
+static LOCK: Mutex<()> = Mutex::new(());
+
+const PATH: &str = "tis-queue.json";
+
+fn update<R>(f: impl FnOnce(&mut Queue) -> R) -> R {
+	let _guard = LOCK.lock();
+	let mut queue: Queue = load_json(PATH);
+	let res = f(&mut queue);
+	save_json(PATH, &queue);
+	res
+}
+
+fn update_item(id: QueueItemId, f: impl FnOnce(&mut QueueItem)) {
+	update(|queue| {
+		if let Some(item) = queue.items.iter_mut().find(|e| e.id == id) {
+			f(item);
+		}
+	});
+}
+
+fn meta_field(bm: &Value, key: &str) -> String {
+	bm.pointer(&format!("/meta/{key}"))
+		.and_then(|e| e.as_str())
+		.unwrap_or("invalid")
+		.to_owned()
+}
+
+/// Give imports queued before server profiles to `server`, the profile the old config was
+/// turned into.
+pub fn adopt_legacy(server: &str) {
+	if list().iter().all(|e| !e.server.is_empty()) {
+		return;
+	}
+
+	update(|queue| {
+		for item in queue.items.iter_mut().filter(|e| e.server.is_empty()) {
+			item.server = server.to_owned();
+		}
+	});
+}
+
+/// Forget about everything that's been uploaded, along with its batch manual.
+fn prune(queue: &mut Queue) {
+	queue.items.retain(|e| {
+		if e.state != QueueState::Done {
+			return true;
+		}
+
+		let _ = fs::remove_file(&e.path);
+		false
+	});
+}
+
+pub fn list() -> Vec<QueueItem> {
+	let _guard = LOCK.lock();
+	load_json::<Queue>(PATH).items
+}
+
+/// Add `bm` to the queue as pending.
+///
+/// If there's already a pending import from the same source to the same server, `bm` is
+/// merged into it instead, so the same scores aren't queued twice.
+pub fn enqueue(source: Option<&str>, server: &str, bm: &Value) -> anyhow::Result<QueueItem> {
+	fs::create_dir_all("batch-manual/queue")?;
+
+	update(|queue| {
+		prune(queue);
+
+		let game = meta_field(bm, "game");
+		let playtype = meta_field(bm, "playtype");
+
+		let pending = queue.items.iter_mut().find(|e| {
+			source.is_some()
+				&& e.source.as_deref() == source
+				&& e.server == server
+				&& e.game == game
+				&& e.playtype == playtype
+				&& e.state == QueueState::Pending
+		});
+
+		if let Some(item) = pending {
+			let merged = match fs::read(&item.path) {
+				Ok(old) => merge(serde_json::from_slice(&old).unwrap_or_default(), bm),
+				Err(_) => bm.clone(),
+			};
+
+			fs::write(
+				&item.path,
+				serde_json::to_string(&merged).expect("must ser"),
+			)?;
+			item.scores = merged["scores"].as_array().map_or(0, |e| e.len());
+			item.added = Utc::now().timestamp_millis();
+
+			return Ok(item.clone());
+		}
+
+		queue.next_id += 1;
+
+		let item = QueueItem {
+			id: queue.next_id,
+			source: source.map(str::to_owned),
+			server: server.to_owned(),
+			game,
+			playtype,
+			scores: bm["scores"].as_array().map_or(0, |e| e.len()),
+			path: PathBuf::from(format!("batch-manual/queue/{}.json", queue.next_id)),
+			added: Utc::now().timestamp_millis(),
+			state: QueueState::Pending,
+			attempts: 0,
+			error: None,
+		};
+
+		fs::write(&item.path, serde_json::to_string(bm).expect("must ser"))?;
+		queue.items.push(item.clone());
+
+		Ok(item)
+	})
+}
+
+/// `bm` with the scores and classes of `old` it doesn't have. Scores are the same if their
+/// identifier and timeAchieved are, in which case the one in `bm` is kept.
+fn merge(old: Value, bm: &Value) -> Value {
+	let mut merged = bm.clone();
+
+	let key = |score: &Value| {
+		(
+			score["identifier"].as_str().map(str::to_owned),
+			score["timeAchieved"].as_i64(),
+		)
+	};
+
+	if let (Some(scores), Value::Array(old)) = (merged["scores"].as_array_mut(), &old["scores"]) {
+		let seen: HashSet<_> = scores.iter().map(key).collect();
+		scores.extend(old.iter().filter(|e| !seen.contains(&key(e))).cloned());
+	}
+
+	if let (Value::Object(old), Some(classes)) =
+		(&old["classes"], merged["classes"].as_object_mut())
+	{
+		for (set, class) in old {
+			classes.entry(set).or_insert_with(|| class.clone());
+		}
+	}
+
+	merged
+}
+
+/// Upload a queued batch manual.
+///
+/// If the server can't be reached it stays pending, to be tried again on the next launch.
+/// Any other error marks it as failed.
+pub async fn upload(
+	client: &TachiClient,
+	id: QueueItemId,
+	user_intent: bool,
+	job: &JobHandle,
+) -> anyhow::Result<Vec<ImportDocument>> {
+	let item = update(|queue| {
+		let item = queue
+			.items
+			.iter_mut()
+			.find(|e| e.id == id)
+			.ok_or_else(|| anyhow!("There's no queued import {id}."))?;
+
+		if item.state == QueueState::Uploading {
+			bail!("Queued import {id} is already being uploaded.");
+		}
+
+		item.state = QueueState::Uploading;
+		item.attempts += 1;
+
+		Ok(item.clone())
+	})?;
+
+	let res = async {
+		let bm: Value = serde_json::from_slice(&fs::read(&item.path)?)?;
+		let documents =
+			import_in_chunks(client, &bm, item.source.as_deref(), user_intent, job).await?;
+
+		if let Some(source) = &item.source {
+			let mut checkpoints = Checkpoints::load();
+			checkpoints.record_import(&client.server().name, source, &bm);
+			checkpoints.save();
+		}
+
+		Ok(documents)
+	}
+	.await;
+
+	update_item(id, |item| match &res {
+		Ok(_) => {
+			item.state = QueueState::Done;
+			item.error = None;
+		}
+		Err(err) => {
+			item.state = if is_offline(err) {
+				QueueState::Pending
+			} else {
+				QueueState::Failed
+			};
+			item.error = Some(err.to_string());
+		}
+	});
+
+	res
+}
+
+/// Upload everything pending for `client`'s server, including anything that was mid-upload
+/// when TIS last closed. Nothing is sent if the token can't import, it's all left for later.
+pub async fn upload_pending(client: &TachiClient) {
+	let server = &client.server().name;
+
+	let pending: Vec<_> = update(|queue| {
+		prune(queue);
+
+		queue
+			.items
+			.iter_mut()
+			.filter(|e| matches!(e.state, QueueState::Pending | QueueState::Uploading))
+			.filter(|e| &e.server == server)
+			.map(|e| {
+				e.state = QueueState::Pending;
+				e.id
+			})
+			.collect()
+	});
+
+	if pending.is_empty() {
+		return;
+	}
+
+	if let Err(err) = check_can_import(client).await {
+		log::warn(format!(
+			"Not uploading {} queued imports to {server}. {err}",
+			pending.len()
+		));
+		return;
+	}
+
+	log::info(format!("Uploading {} queued imports.", pending.len()));
+
+	for id in pending {
+		if let Err(err) = upload(client, id, false, &JobHandle::detached(JobKind::Import)).await {
+			log::error(format!("Queued import {id} failed: {err}"));
+
+			if is_offline(&err) {
+				log::warn("Still can't reach the server, leaving the rest for later.".into());
+				return;
+			}
+		}
+	}
+}
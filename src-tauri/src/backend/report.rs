@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use super::log::Logger;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+	InvalidRow,
+	ChartNotFound,
+	RandomChart,
+	UnknownGamemode,
+	InvalidRandom,
+	InvalidLamp,
+	AutoScratch,
+	Autoplay,
+	InvalidHitWindows,
+	UnknownGauge,
+	LnModeUnused,
+	LnModeNotPicked,
+	NoPmsPlaytype,
+	UnknownCourse,
+}
+
+impl fmt::Display for SkipReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			SkipReason::InvalidRow => "unreadable row in score database",
+			SkipReason::ChartNotFound => "chart not in chart database",
+			SkipReason::RandomChart => "chart has #RANDOM declarations",
+			SkipReason::UnknownGamemode => "unsupported gamemode",
+			SkipReason::InvalidRandom => "invalid or unfair random option",
+			SkipReason::InvalidLamp => "invalid lamp",
+			SkipReason::AutoScratch => "negative bp, probably autoscratch",
+			SkipReason::Autoplay => "played with autoplay",
+			SkipReason::InvalidHitWindows => "non-standard hit windows",
+			SkipReason::UnknownGauge => "unknown gauge",
+			SkipReason::LnModeUnused => "LN mode the chart doesn't use",
+			SkipReason::LnModeNotPicked => "LN mode not picked",
+			SkipReason::NoPmsPlaytype => "PMS, but no PMS playtype was picked",
+			SkipReason::UnknownCourse => "course TIS doesn't know of",
+		})
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedScore {
+	pub reason: SkipReason,
+	/// Chart hash, if we got far enough to read one.
+	pub identifier: Option<String>,
+	pub title: Option<String>,
+}
+
+/// What happened to every row a converter looked at.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionReport {
+	/// Filename of the chart database, so "chart not found" can say where we looked.
+	pub chart_db: Option<String>,
+	pub converted: usize,
+	/// Scores left out because they were already imported. Not counted as skipped.
+	pub already_imported: usize,
+	pub skip_counts: BTreeMap<SkipReason, usize>,
+	pub skipped: Vec<SkippedScore>,
+	/// Human readable version of `skip_counts`, filled in by [`ConversionReport::finish`].
+	pub summary: Vec<String>,
+}
+
+impl ConversionReport {
+	pub fn skip(&mut self, reason: SkipReason, identifier: Option<&str>, title: Option<&str>) {
+		*self.skip_counts.entry(reason).or_default() += 1;
+
+		self.skipped.push(SkippedScore {
+			reason,
+			identifier: identifier.map(str::to_owned),
+			title: title.map(str::to_owned),
+		});
+	}
+
+	pub fn total_skipped(&self) -> usize {
+		self.skip_counts.values().sum()
+	}
+
+	/// One line per skip reason, e.g. "37 skipped: chart not in songdata.db".
+	fn summarise(&self) -> Vec<String> {
+		self.skip_counts
+			.iter()
+			.map(|(reason, count)| match (reason, &self.chart_db) {
+				(SkipReason::ChartNotFound, Some(db)) => {
+					format!("{count} skipped: chart not in {db}")
+				}
+				_ => format!("{count} skipped: {reason}"),
+			})
+			.collect()
+	}
+
+	/// Record how many scores made it through and log the summary.
+	pub fn finish(&mut self, converted: usize, log: &Logger) {
+		self.converted = converted;
+		self.summary = self.summarise();
+
+		log.info(format!(
+			"Converted {} scores, skipped {}.",
+			self.converted,
+			self.total_skipped()
+		));
+
+		if self.already_imported > 0 {
+			log.info(format!(
+				"Left out {} scores that were already imported. Do a full resync to send them again.",
+				self.already_imported
+			));
+		}
+
+		for line in &self.summary {
+			log.warn(line.clone());
+		}
+	}
+}
+
+/// A converter's output, along with the report of what it left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Converted<T> {
+	pub output: T,
+	pub report: ConversionReport,
+}
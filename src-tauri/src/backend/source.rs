@@ -0,0 +1,194 @@
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use rusqlite::Connection;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+	beatoraja::Beatoraja,
+	config::TISConfig,
+	courses::Course,
+	jobs::{JobHandle, JobKind, JobStatus},
+	log::Logger,
+	lr2::LR2,
+	report::Converted,
+	sqlite::{attach_snapshot_sqlite3, attach_sqlite3, connect_sqlite3, snapshot_sqlite3},
+	usc::USC,
+};
+
+/// Everything a converter gets handed besides its own config.
+pub struct ConvertContext {
+	pub log: Logger,
+	/// Playtype -> newest `timeAchieved` already on Tachi. Empty for a full resync.
+	pub since: HashMap<String, i64>,
+	/// Work from an in-memory copy of each database instead of reading the live file.
+	pub snapshot: bool,
+	pub job: JobHandle,
+	/// Dan courses to look for among course scores. None unless they're loaded in, with
+	/// [`super::courses::load_courses`].
+	pub courses: Vec<Course>,
+}
+
+impl ConvertContext {
+	pub fn new(log: Logger) -> Self {
+		Self {
+			log,
+			since: HashMap::new(),
+			snapshot: false,
+			job: JobHandle::detached(JobKind::Convert),
+			courses: vec![],
+		}
+	}
+
+	/// Call once per row read. Reports progress every so often, and bails with
+	/// [`super::jobs::Cancelled`] if the job has been cancelled.
+	pub fn row_scanned(&self, rows_scanned: usize, scores_kept: usize) -> anyhow::Result<()> {
+		if rows_scanned % 500 == 0 {
+			self.job.report(JobStatus::Converting {
+				rows_scanned,
+				scores_kept,
+			});
+			self.job.check_cancelled()?;
+		}
+
+		Ok(())
+	}
+
+	/// Open one of the game's databases, taking a snapshot first if asked to.
+	pub fn open_db(&self, path: &Path) -> anyhow::Result<Connection> {
+		if self.snapshot {
+			self.log.debug(format!("Taking a snapshot of {path:?}."));
+			snapshot_sqlite3(path)
+		} else {
+			connect_sqlite3(path)
+		}
+	}
+
+	/// Attach another of the game's databases to `db` as `name`, taking a snapshot first if
+	/// asked to.
+	pub fn attach_db(&self, db: &mut Connection, path: &Path, name: &str) -> anyhow::Result<()> {
+		if self.snapshot {
+			self.log.debug(format!("Taking a snapshot of {path:?}."));
+			attach_snapshot_sqlite3(db, path, name)
+		} else {
+			attach_sqlite3(db, path, name)
+		}
+	}
+
+	/// Open a score database with its chart database attached as `chart`, so the two can be
+	/// joined in one query.
+	pub fn open_with_charts(
+		&self,
+		score_path: &Path,
+		chart_path: &Path,
+	) -> anyhow::Result<Connection> {
+		let mut db = self.open_db(score_path)?;
+		self.attach_db(&mut db, chart_path, "chart")?;
+
+		Ok(db)
+	}
+
+	/// Whether a score set at `time_achieved` still needs importing.
+	pub fn is_new(&self, playtype: &str, time_achieved: i64) -> bool {
+		self.since
+			.get(playtype)
+			.is_none_or(|since| time_achieved > *since)
+	}
+}
+
+/// A game (or game client) whose local database we know how to turn into batch manuals.
+///
+/// Adding a new game means implementing this and adding it to [`SOURCES`].
+pub trait ScoreSource: Sync {
+	/// Used to pick this source in the `convert` command and the CLI.
+	const ID: &'static str;
+
+	type Config: Serialize + DeserializeOwned + Clone;
+	type Output: Serialize;
+
+	/// Whether `db` looks like a score database for this source.
+	fn detect(db: &Connection) -> bool;
+
+	fn convert(
+		config: &Self::Config,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<Converted<Self::Output>>;
+
+	/// Where the last used config for this source is kept in `tis-config.json`.
+	fn saved_config(config: &mut TISConfig) -> &mut Option<Self::Config>;
+
+	/// The file the game writes new scores to, for watch mode. `None` if this source can't
+	/// be imported incrementally, as re-sending everything after each session isn't useful.
+	fn score_db(_config: &Self::Config) -> Option<&Path> {
+		None
+	}
+}
+
+/// [`ScoreSource`] with the types erased to JSON, so that sources can live in one list.
+pub trait AnySource: Sync {
+	fn id(&self) -> &'static str;
+
+	fn matches(&self, db: &Connection) -> bool;
+
+	fn convert_json(
+		&self,
+		opts: serde_json::Value,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<serde_json::Value>;
+
+	fn save_opts(&self, config: &mut TISConfig, opts: serde_json::Value) -> anyhow::Result<()>;
+
+	/// The score database to watch and the saved options to convert it with, if this source
+	/// has been set up and supports watching.
+	fn watch_target(&self, config: &mut TISConfig) -> Option<(PathBuf, serde_json::Value)>;
+}
+
+impl<S: ScoreSource> AnySource for S {
+	fn id(&self) -> &'static str {
+		S::ID
+	}
+
+	fn matches(&self, db: &Connection) -> bool {
+		S::detect(db)
+	}
+
+	fn convert_json(
+		&self,
+		opts: serde_json::Value,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<serde_json::Value> {
+		let opts: S::Config = serde_json::from_value(opts)
+			.map_err(|e| anyhow!("Invalid options for {}: {e}", S::ID))?;
+
+		Ok(serde_json::to_value(S::convert(&opts, ctx)?)?)
+	}
+
+	fn save_opts(&self, config: &mut TISConfig, opts: serde_json::Value) -> anyhow::Result<()> {
+		*S::saved_config(config) = Some(serde_json::from_value(opts)?);
+
+		Ok(())
+	}
+
+	fn watch_target(&self, config: &mut TISConfig) -> Option<(PathBuf, serde_json::Value)> {
+		let saved = S::saved_config(config).as_ref()?;
+		let path = S::score_db(saved)?.to_path_buf();
+
+		Some((path, serde_json::to_value(saved).ok()?))
+	}
+}
+
+pub static SOURCES: &[&dyn AnySource] = &[&LR2, &Beatoraja, &USC];
+
+pub fn find_source(id: &str) -> Option<&'static dyn AnySource> {
+	SOURCES.iter().copied().find(|e| e.id() == id)
+}
+
+/// Work out which source the score database at `path` belongs to.
+pub fn detect_source(path: &Path) -> anyhow::Result<Option<&'static dyn AnySource>> {
+	let db = connect_sqlite3(path)?;
+
+	Ok(SOURCES.iter().copied().find(|e| e.matches(&db)))
+}
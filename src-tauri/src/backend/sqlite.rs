@@ -1,8 +1,15 @@
-use std::path::Path;
+use std::{path::Path, thread, time::Duration};
 
 use anyhow::bail;
+use rusqlite::{
+	backup::{Backup, StepResult},
+	Connection, DatabaseName, OpenFlags,
+};
 
-pub fn connect_sqlite3(path: &Path) -> anyhow::Result<rusqlite::Connection> {
+/// How long to wait on the game's locks before giving up with SQLITE_BUSY.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn ensure_exists(path: &Path) -> anyhow::Result<()> {
 	if !path.exists() {
 		bail!(
 			"Could not find a sqlite db in {:?}",
@@ -12,7 +19,87 @@ pub fn connect_sqlite3(path: &Path) -> anyhow::Result<rusqlite::Connection> {
 		);
 	}
 
-	let cxn = rusqlite::Connection::open(path)?;
+	Ok(())
+}
+
+/// Open a game's database read-only, so that we can never get in the way of its writes.
+pub fn connect_sqlite3(path: &Path) -> anyhow::Result<Connection> {
+	ensure_exists(path)?;
+
+	let cxn = Connection::open_with_flags(
+		path,
+		OpenFlags::SQLITE_OPEN_READ_ONLY
+			| OpenFlags::SQLITE_OPEN_URI
+			| OpenFlags::SQLITE_OPEN_NO_MUTEX,
+	)?;
+	cxn.busy_timeout(BUSY_TIMEOUT)?;
 
 	Ok(cxn)
 }
+
+/// Copy the database at `path` into the `to` schema of `dst` with SQLite's online backup API.
+///
+/// The copy is taken in a single step, so it's a consistent view of the database even if
+/// the game is writing to it at the same time.
+fn copy_into(path: &Path, dst: &mut Connection, to: DatabaseName) -> anyhow::Result<()> {
+	let src = connect_sqlite3(path)?;
+	let backup = Backup::new_with_names(&src, DatabaseName::Main, dst, to)?;
+	let mut retries = 0;
+
+	loop {
+		match backup.step(-1)? {
+			StepResult::Done => return Ok(()),
+			_ if retries >= 10 => {
+				bail!("Couldn't take a snapshot of {path:?}, the database stayed locked.")
+			}
+			_ => {
+				retries += 1;
+				thread::sleep(Duration::from_millis(250));
+			}
+		}
+	}
+}
+
+/// An in-memory copy of the database at `path`. See [`copy_into`].
+pub fn snapshot_sqlite3(path: &Path) -> anyhow::Result<Connection> {
+	let mut dst = Connection::open_in_memory()?;
+	copy_into(path, &mut dst, DatabaseName::Main)?;
+
+	Ok(dst)
+}
+
+/// Attach the database at `path` to `db` as `schema`.
+///
+/// Attached databases are opened with the same flags as `db`, so this is read-only if `db` is.
+pub fn attach_sqlite3(db: &Connection, path: &Path, schema: &str) -> anyhow::Result<()> {
+	ensure_exists(path)?;
+
+	db.execute("ATTACH DATABASE ?1 AS ?2", (path.to_string_lossy(), schema))?;
+
+	Ok(())
+}
+
+/// Attach an in-memory copy of the database at `path` to `db` as `schema`.
+pub fn attach_snapshot_sqlite3(
+	db: &mut Connection,
+	path: &Path,
+	schema: &str,
+) -> anyhow::Result<()> {
+	db.execute("ATTACH DATABASE ':memory:' AS ?1", [schema])?;
+	copy_into(path, db, DatabaseName::Attached(schema))
+}
+
+pub fn table_has_columns(db: &Connection, table: &str, columns: &[&str]) -> bool {
+	let Ok(mut stmt) = db.prepare(&format!("PRAGMA table_info({table})")) else {
+		return false;
+	};
+
+	let Ok(names) = stmt
+		.query_map([], |row| row.get::<_, String>("name"))
+		.and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+	else {
+		return false;
+	};
+
+	columns.iter().all(|c| names.iter().any(|n| n == c))
+}
@@ -0,0 +1,33 @@
+//! Small JSON files kept next to `tis-config.json` for state that isn't configuration.
+
+use std::fs;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::log;
+
+pub fn load_json<T: DeserializeOwned + Default>(path: &str) -> T {
+	if fs::exists(path).is_ok_and(|e| !e) {
+		return T::default();
+	}
+
+	match fs::read(path).map(|v| serde_json::from_slice(&v)) {
+		Ok(Ok(v)) => v,
+		Ok(Err(err)) => {
+			log::warn(format!("Failed to parse {path}, starting afresh. {err:?}"));
+			T::default()
+		}
+		Err(err) => {
+			log::warn(format!("Failed to read {path}. {err:?}"));
+			T::default()
+		}
+	}
+}
+
+pub fn save_json<T: Serialize>(path: &str, value: &T) {
+	let res = fs::write(path, serde_json::to_string_pretty(value).expect("must ser"));
+
+	if let Err(err) = res {
+		log::warn(format!("Failed to save {path}. {err:?}"));
+	}
+}
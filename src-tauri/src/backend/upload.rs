@@ -0,0 +1,246 @@
+//! Uploading batch manuals too big for one request as a series of smaller imports.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::bail;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+	client::TachiClient,
+	import::{import_batch_manual, ImportDocument},
+	jobs::JobHandle,
+	log,
+	store::{load_json, save_json},
+	validate::{count, validate_batch_manual, Problem},
+};
+
+/// How many chunks of each unfinished chunked upload made it to Tachi, keyed by a hash of
+/// the whole batch manual. Importing the same batch manual again carries on from there.
+///
+/// Converting again almost always gives a different batch manual, so in practice this only
+/// resumes retries of the same queued import.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct UploadProgress(HashMap<String, usize>);
+
+/// Several uploads can be running at once, so every change to the progress file goes
+/// through here.
+static LOCK: Mutex<()> = Mutex::new(());
+
+impl UploadProgress {
+	const PATH: &str = "tis-upload-progress.json";
+
+	fn get(key: &str) -> usize {
+		let _guard = LOCK.lock();
+		load_json::<Self>(Self::PATH)
+			.0
+			.get(key)
+			.copied()
+			.unwrap_or(0)
+	}
+
+	/// Record that `done` chunks are in, or forget about the upload if `done` is `None`.
+	fn set(key: &str, done: Option<usize>) {
+		let _guard = LOCK.lock();
+		let mut progress: Self = load_json(Self::PATH);
+
+		match done {
+			Some(done) => progress.0.insert(key.to_owned(), done),
+			None => progress.0.remove(key),
+		};
+
+		save_json(Self::PATH, &progress);
+	}
+}
+
+/// FNV-1a, so the key stays the same between builds.
+fn hash_key(bm: &Value) -> String {
+	let hash = serde_json::to_string(bm)
+		.expect("must ser")
+		.bytes()
+		.fold(0xcbf29ce484222325u64, |hash, b| {
+			(hash ^ b as u64).wrapping_mul(0x100000001b3)
+		});
+
+	format!("{hash:016x}")
+}
+
+/// Split `bm` into batch manuals of roughly `max_bytes` each, oldest scores first.
+///
+/// Every chunk gets the same `meta`. Classes are only sent with the last chunk, once all
+/// the scores they were earned with are in.
+pub fn split_batch_manual(bm: &Value, max_bytes: usize) -> Vec<Value> {
+	let mut scores = bm["scores"].as_array().cloned().unwrap_or_default();
+	// Scores without a time (LR2) sort first, in the order they came in.
+	scores.sort_by_key(|e| e.get("timeAchieved").and_then(|e| e.as_i64()));
+
+	let mut base = bm.clone();
+	base["scores"] = Value::Array(vec![]);
+	let classes = base.as_object_mut().and_then(|e| e.remove("classes"));
+
+	let budget = max_bytes.saturating_sub(base.to_string().len());
+
+	let mut chunks: Vec<Vec<Value>> = vec![];
+	let mut current = vec![];
+	let mut size = 0;
+
+	for score in scores {
+		let len = score.to_string().len() + 1;
+
+		if !current.is_empty() && size + len > budget {
+			chunks.push(std::mem::take(&mut current));
+			size = 0;
+		}
+
+		size += len;
+		current.push(score);
+	}
+
+	if !current.is_empty() || chunks.is_empty() {
+		chunks.push(current);
+	}
+
+	let count = chunks.len();
+
+	chunks
+		.into_iter()
+		.enumerate()
+		.map(|(i, scores)| {
+			let mut chunk = base.clone();
+			chunk["scores"] = Value::Array(scores);
+			if let Some(classes) = classes.as_ref().filter(|_| i + 1 == count) {
+				chunk["classes"] = classes.clone();
+			}
+			chunk
+		})
+		.collect()
+}
+
+/// `bm` without the scores Tachi would reject, logging every problem with them.
+///
+/// Fails if the problem is with the batch manual as a whole, like an invalid meta.
+/// `bm` without the scores Tachi would reject, and the problems that got them left out.
+fn check_batch_manual(bm: &Value) -> anyhow::Result<(Value, Vec<Problem>)> {
+	let problems = validate_batch_manual(bm);
+
+	if problems.is_empty() {
+		return Ok((bm.clone(), vec![]));
+	}
+
+	let general: Vec<_> = problems.iter().filter(|e| e.index.is_none()).collect();
+
+	if !general.is_empty() {
+		let mut msg = format!(
+			"Not importing, as the batch manual has {}:",
+			count(general.len(), "problem")
+		);
+		for problem in general {
+			msg.push_str(&format!("\n{problem}"));
+		}
+
+		bail!(msg)
+	}
+
+	for problem in &problems {
+		log::warn(problem.to_string());
+	}
+
+	let (errors, _): (Vec<_>, Vec<_>) = problems.into_iter().partition(|e| e.is_error());
+	let bad: HashSet<_> = errors.iter().filter_map(|e| e.index).collect();
+
+	if bad.is_empty() {
+		return Ok((bm.clone(), vec![]));
+	}
+
+	log::warn(format!(
+		"Leaving out {} Tachi would reject, see above. Importing the rest.",
+		count(bad.len(), "score")
+	));
+
+	let mut bm = bm.clone();
+	if let Some(scores) = bm["scores"].as_array_mut() {
+		*scores = std::mem::take(scores)
+			.into_iter()
+			.enumerate()
+			.filter(|(i, _)| !bad.contains(i))
+			.map(|(_, e)| e)
+			.collect();
+	}
+
+	Ok((bm, errors))
+}
+
+/// Import `bm`, split up into chunks no bigger than the client allows, one after another.
+///
+/// It's checked with [`validate_batch_manual`] first. Scores with errors are left out, and
+/// listed in the first document returned, while scores with only warnings are still sent.
+///
+/// If a chunk fails, the ones before it stay imported, and importing the same batch manual
+/// again starts from the chunk that failed. Returns the documents of every chunk imported
+/// by this call.
+pub async fn import_in_chunks(
+	client: &TachiClient,
+	bm: &Value,
+	source: Option<&str>,
+	user_intent: bool,
+	job: &JobHandle,
+) -> anyhow::Result<Vec<ImportDocument>> {
+	let (bm, left_out) = check_batch_manual(bm)?;
+	let bm = &bm;
+
+	let chunks = split_batch_manual(bm, client.options().max_chunk_bytes);
+
+	if let [chunk] = chunks.as_slice() {
+		let mut doc = import_batch_manual(client, chunk, source, user_intent, job).await?;
+		doc.left_out = left_out;
+		return Ok(vec![doc]);
+	}
+
+	let key = hash_key(bm);
+	let done = UploadProgress::get(&key);
+
+	if done > 0 {
+		log::info(format!(
+			"Resuming upload from chunk {} of {}.",
+			done + 1,
+			chunks.len()
+		));
+	}
+
+	let mut documents = vec![];
+
+	for (i, chunk) in chunks.iter().enumerate().skip(done) {
+		log::info(format!("Uploading chunk {} of {}...", i + 1, chunks.len()));
+
+		match import_batch_manual(client, chunk, source, user_intent, job).await {
+			Ok(doc) => documents.push(doc),
+			Err(err) => {
+				let msg = format!(
+					"Chunk {} of {} failed: {err} Import again to carry on from there.",
+					i + 1,
+					chunks.len()
+				);
+				return Err(err.context(msg));
+			}
+		}
+
+		UploadProgress::set(&key, Some(i + 1));
+	}
+
+	UploadProgress::set(&key, None);
+
+	if let Some(doc) = documents.first_mut() {
+		doc.left_out = left_out;
+	}
+
+	log::info(format!(
+		"Uploaded {} chunks. New Scores: {} | Failed {}",
+		chunks.len(),
+		documents.iter().map(|e| e.score_ids.len()).sum::<usize>(),
+		documents.iter().map(|e| e.errors.len()).sum::<usize>(),
+	));
+
+	Ok(documents)
+}
@@ -1,4 +1,7 @@
+use std::path::Path;
+
 use anyhow::bail;
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 
 use super::{
@@ -6,9 +9,11 @@ use super::{
 		BatchManualClasses, BatchManualMeta, USCBatchManual, USCBatchManualScore, USCGaugeMod,
 		USCJudgements, USCLamp, USCNoteMod, USCOptionalMetrics, USCScoreMeta,
 	},
-	config::{USCConfig, SERVICE_NAME},
-	log,
-	sqlite::connect_sqlite3,
+	config::{TISConfig, USCConfig},
+	log::Logger,
+	report::{ConversionReport, Converted, SkipReason},
+	source::{ConvertContext, ScoreSource},
+	sqlite::table_has_columns,
 };
 
 #[derive(Debug, Clone)]
@@ -44,6 +49,16 @@ pub enum USCPlaytype {
 	Keyboard,
 }
 
+impl USCPlaytype {
+	/// The Tachi playtype for scores set on this device.
+	pub fn playtype(&self) -> &'static str {
+		match self {
+			USCPlaytype::Controller => "Controller",
+			USCPlaytype::Keyboard => "Keyboard",
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct HitWindows {
 	perfect: i32,
@@ -80,18 +95,18 @@ impl HitWindows {
 }
 
 #[must_use = "actually check this you muppet"]
-fn check_hit_windows(score_name: &str, windows: HitWindows) -> bool {
+fn check_hit_windows(score_name: &str, windows: HitWindows, log: &Logger) -> bool {
 	if windows == HitWindows::DEFAULT {
 		return true;
 	}
 
 	if windows == HitWindows::LEGACY {
-		log::debug("Allowing score with legacy hit windows to be imported anyway.".to_string());
+		log.debug("Allowing score with legacy hit windows to be imported anyway.".to_string());
 		return true;
 	}
 
 	if windows == HitWindows::BUGGED {
-		log::warn(
+		log.warn(
 			format!(
 			"Score ({score_name}) detected with bugged hit windows! A game update has caused the new hitwindows to partially apply. YOU SHOULD GO INTO SETTINGS AND RESET YOUR HIT WINDOWS, AS YOU ARE PLAYING ON TIGHTER HIT WINDOWS THAN NORMAL!
 For compatibility reasons, this score will be accepted."
@@ -130,22 +145,55 @@ fn get_notemod(score: &ScoreRow) -> USCNoteMod {
 	}
 }
 
+pub struct USC;
+
+impl ScoreSource for USC {
+	const ID: &'static str = "usc";
+
+	type Config = USCConfig;
+	type Output = USCBatchManual;
+
+	fn detect(db: &Connection) -> bool {
+		table_has_columns(db, "Database", &["version"])
+			&& table_has_columns(db, "Scores", &["chart_hash", "crit", "near"])
+	}
+
+	fn convert(
+		config: &USCConfig,
+		ctx: &ConvertContext,
+	) -> anyhow::Result<Converted<USCBatchManual>> {
+		convert_usc_db(config, ctx)
+	}
+
+	fn saved_config(config: &mut TISConfig) -> &mut Option<USCConfig> {
+		&mut config.usc_db
+	}
+
+	fn score_db(config: &USCConfig) -> Option<&Path> {
+		Some(&config.db_path)
+	}
+}
+
 pub fn convert_usc_db(
 	USCConfig { db_path, playtype }: &USCConfig,
-) -> anyhow::Result<USCBatchManual> {
-	let db = connect_sqlite3(db_path)?;
+	ctx: &ConvertContext,
+) -> anyhow::Result<Converted<USCBatchManual>> {
+	let log = &ctx.log;
+	let mut report = ConversionReport::default();
+
+	let db = ctx.open_db(db_path)?;
 
 	let version: i32 =
 		db.query_row("SELECT version FROM Database", [], |row| row.get("version"))?;
 
 	if version < 19 {
-		log::error(format!("The version of your maps.db is {version}, which is below the minimum of 19. Update your game. Refusing to run."));
+		log.error(format!("The version of your maps.db is {version}, which is below the minimum of 19. Update your game. Refusing to run."));
 
 		bail!("The version of your maps.db is {version}, which is below the minimum of 19. Update your game. Refusing to run.")
 	}
 
 	if version > 20 {
-		log::error(
+		log.error(
 			format!(
 			"The version of your maps.db is {version}, which is a version after what this tool supports (20). It might not be safe to convert this. Report this, and I'll update the tool to work for the later version!"
 		));
@@ -188,11 +236,14 @@ pub fn convert_usc_db(
 
 	let mut output_scores = vec![];
 
-	for score in scores {
+	for (i, score) in scores.enumerate() {
+		ctx.row_scanned(i + 1, output_scores.len())?;
+
 		let score = match score {
 			Ok(v) => v,
 			Err(err) => {
-				log::warn(format!("Invalid score in DB: {err}. Skipping."));
+				log.debug(format!("Invalid score in DB: {err}. Skipping."));
+				report.skip(SkipReason::InvalidRow, None, None);
 				continue;
 			}
 		};
@@ -202,13 +253,24 @@ pub fn convert_usc_db(
 			score.title, score.diff_shortname, score.score
 		);
 
-		if !check_hit_windows(&name, score.hit_windows) {
-			log::error(format!("Invalid hit windows for score {name}. Skipping."));
+		if !ctx.is_new(playtype.playtype(), score.timestamp as i64 * 1000) {
+			report.already_imported += 1;
+			continue;
+		}
+
+		if !check_hit_windows(&name, score.hit_windows, log) {
+			log.debug(format!("Invalid hit windows for score {name}. Skipping."));
+			report.skip(
+				SkipReason::InvalidHitWindows,
+				Some(&score.chart_hash),
+				Some(&name),
+			);
 			continue;
 		}
 
 		if score.auto_flags != 0 {
-			log::debug("Skipping".to_string());
+			log.debug(format!("Skipping {name} as it was played with autoplay."));
+			report.skip(SkipReason::Autoplay, Some(&score.chart_hash), Some(&name));
 			continue;
 		}
 
@@ -231,9 +293,14 @@ pub fn convert_usc_db(
 					0 => Some(USCGaugeMod::Normal),
 					1 => Some(USCGaugeMod::Hard),
 					unknown => {
-						log::warn(format!(
+						log.debug(format!(
 							"Ignoring score on {name} as it has a gauge mod of {unknown}."
 						));
+						report.skip(
+							SkipReason::UnknownGauge,
+							Some(&score.chart_hash),
+							Some(&name),
+						);
 						continue;
 					}
 				},
@@ -248,17 +315,14 @@ pub fn convert_usc_db(
 		output_scores.push(score);
 	}
 
-	Ok(USCBatchManual {
-		classes: BatchManualClasses::default(),
-		meta: BatchManualMeta {
-			game: "usc".into(),
-			playtype: match playtype {
-				USCPlaytype::Controller => "Controller".into(),
-				USCPlaytype::Keyboard => "Keyboard".into(),
-			},
-			service: SERVICE_NAME.into(),
-			version: None,
+	report.finish(output_scores.len(), log);
+
+	Ok(Converted {
+		output: USCBatchManual {
+			classes: BatchManualClasses::default(),
+			meta: BatchManualMeta::new("usc", playtype.playtype()),
+			scores: output_scores,
 		},
-		scores: output_scores,
+		report,
 	})
 }
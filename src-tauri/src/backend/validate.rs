@@ -0,0 +1,336 @@
+//! Checks a batch manual the way Tachi would, so obviously broken scores are caught before
+//! an upload rather than after. Scores that only look inconsistent are warned about, since
+//! the games don't always keep their numbers in step.
+
+use std::fmt;
+
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::batch_manual::{
+	BMSBatchManualScore, BMSLamp, BatchManualMeta, PMSBatchManualScore, USCBatchManualScore,
+	USCLamp,
+};
+
+const USC_MAX_SCORE: u64 = 10_000_000;
+
+/// Anything before 1973 in milliseconds is probably a timestamp in seconds.
+const MIN_TIME_ACHIEVED: i64 = 100_000_000_000;
+
+/// Allow for clocks being a little off.
+const MAX_CLOCK_SKEW_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+	/// Tachi would reject it.
+	Error,
+	/// Looks wrong, but Tachi takes it. A beatoraja best, for one, has the best lamp and the
+	/// best judgements, which can be from different plays.
+	Warning,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+	/// Index into `scores`, if this is about one score.
+	pub index: Option<usize>,
+	pub identifier: Option<String>,
+	pub message: String,
+	pub severity: Severity,
+}
+
+impl Problem {
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+}
+
+impl fmt::Display for Problem {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.severity == Severity::Warning {
+			f.write_str("(warning) ")?;
+		}
+
+		match (self.index, &self.identifier) {
+			(Some(i), Some(id)) => write!(f, "scores[{i}] ({id}): {}", self.message),
+			(Some(i), None) => write!(f, "scores[{i}]: {}", self.message),
+			_ => f.write_str(&self.message),
+		}
+	}
+}
+
+/// Collects the problems with a single score.
+struct ScoreChecker<'a> {
+	index: usize,
+	identifier: &'a str,
+	problems: &'a mut Vec<Problem>,
+}
+
+impl ScoreChecker<'_> {
+	fn push(&mut self, message: String, severity: Severity) {
+		self.problems.push(Problem {
+			index: Some(self.index),
+			identifier: Some(self.identifier.to_owned()),
+			message,
+			severity,
+		});
+	}
+
+	fn problem(&mut self, message: String) {
+		self.push(message, Severity::Error);
+	}
+
+	fn warning(&mut self, message: String) {
+		self.push(message, Severity::Warning);
+	}
+
+	fn non_negative(&mut self, name: &str, value: Option<i32>) {
+		if let Some(value) = value.filter(|e| *e < 0) {
+			self.problem(format!("{name} can't be negative, got {value}."));
+		}
+	}
+
+	fn identifier(&mut self, match_type: &str, hash_lengths: &[usize], hash_type: &str) {
+		let id = self.identifier;
+
+		if match_type == "tachiSongID" {
+			if id.is_empty() || !id.bytes().all(|e| e.is_ascii_digit()) {
+				self.problem(format!("tachiSongID must be a number, got '{id}'."));
+			}
+		} else if match_type == hash_type {
+			if !hash_lengths.contains(&id.len()) || !id.bytes().all(|e| e.is_ascii_hexdigit()) {
+				self.problem(format!(
+					"{hash_type} must be a hex hash of length {}, got '{id}'.",
+					hash_lengths
+						.iter()
+						.map(|e| e.to_string())
+						.collect::<Vec<_>>()
+						.join(" or ")
+				));
+			}
+		} else {
+			self.problem(format!("Unsupported matchType '{match_type}'."));
+		}
+	}
+
+	fn time_achieved(&mut self, time: Option<i64>) {
+		let Some(time) = time else {
+			return;
+		};
+
+		if time < 0 {
+			self.problem(format!("timeAchieved can't be negative, got {time}."));
+		} else if time < MIN_TIME_ACHIEVED {
+			self.warning(format!(
+				"timeAchieved {time} looks like seconds, it should be milliseconds."
+			));
+		} else if time > Utc::now().timestamp_millis() + MAX_CLOCK_SKEW_MS {
+			self.warning(format!("timeAchieved {time} is in the future."));
+		}
+	}
+}
+
+/// BMS and PMS scores only differ in what their judgements are called, so everything past
+/// the identifier is checked here. `judgements` starts with the pgreat and great equivalents,
+/// and `optional` is fast, slow, maxCombo and bp, then the gauge.
+fn check_ex_score(
+	c: &mut ScoreChecker,
+	score: u64,
+	lamp: &BMSLamp,
+	judgements: Option<[(&str, Option<i32>); 5]>,
+	optional: Option<([Option<i32>; 4], Option<f64>)>,
+) {
+	if let Some(j) = judgements {
+		for (name, value) in j {
+			c.non_negative(name, value);
+		}
+
+		if let (BMSLamp::FullCombo, (bad_name, Some(bad))) = (lamp, j[3]) {
+			if bad > 0 {
+				c.warning(format!(
+					"Lamp is FULL COMBO, but there are {bad} {bad_name}s."
+				));
+			}
+		}
+
+		if let [(pgreat_name, Some(pgreat)), (great_name, Some(great)), ..] = j {
+			let ex = pgreat as i64 * 2 + great as i64;
+			if ex >= 0 && score != ex as u64 {
+				c.warning(format!(
+					"score is {score}, but {pgreat_name} * 2 + {great_name} is {ex}."
+				));
+			}
+		}
+	}
+
+	if let Some((counts, gauge)) = optional {
+		for (name, value) in ["fast", "slow", "maxCombo", "bp"].into_iter().zip(counts) {
+			c.non_negative(name, value);
+		}
+
+		// beatoraja counts empty poors in bp, and they don't break combo, so this can happen.
+		if let (BMSLamp::FullCombo, Some(bp)) = (lamp, counts[3]) {
+			if bp > 0 {
+				c.warning(format!("Lamp is FULL COMBO, but bp is {bp}."));
+			}
+		}
+
+		if let Some(gauge) = gauge.filter(|e| !(0.0..=100.0).contains(e)) {
+			c.problem(format!("gauge must be between 0 and 100, got {gauge}."));
+		}
+	}
+}
+
+fn check_bms(score: &BMSBatchManualScore, c: &mut ScoreChecker) {
+	c.identifier(&score.match_type, &[32, 64], "bmsChartHash");
+	c.time_achieved(score.time_achieved);
+
+	check_ex_score(
+		c,
+		score.score,
+		&score.lamp,
+		score.judgements.as_ref().map(|j| {
+			[
+				("pgreat", j.pgreat),
+				("great", j.great),
+				("good", j.good),
+				("bad", j.bad),
+				("poor", j.poor),
+			]
+		}),
+		score
+			.optional
+			.as_ref()
+			.map(|e| ([e.fast, e.slow, e.max_combo, e.bp], e.gauge)),
+	);
+}
+
+fn check_pms(score: &PMSBatchManualScore, c: &mut ScoreChecker) {
+	c.identifier(&score.match_type, &[32, 64], "pmsChartHash");
+	c.time_achieved(score.time_achieved);
+
+	check_ex_score(
+		c,
+		score.score,
+		&score.lamp,
+		score.judgements.as_ref().map(|j| {
+			[
+				("cool", j.cool),
+				("great", j.great),
+				("good", j.good),
+				("bad", j.bad),
+				("poor", j.poor),
+			]
+		}),
+		score
+			.optional
+			.as_ref()
+			.map(|e| ([e.fast, e.slow, e.max_combo, e.bp], e.gauge)),
+	);
+}
+
+fn check_usc(score: &USCBatchManualScore, c: &mut ScoreChecker) {
+	c.identifier(&score.match_type, &[40], "uscChartHash");
+	c.time_achieved(score.time_achieved);
+
+	if score.score > USC_MAX_SCORE {
+		c.problem(format!(
+			"score can't be more than {USC_MAX_SCORE}, got {}.",
+			score.score
+		));
+	}
+
+	let miss = score.judgements.as_ref().and_then(|e| e.miss);
+
+	if let Some(j) = &score.judgements {
+		for (name, value) in [("critical", j.critical), ("near", j.near), ("miss", j.miss)] {
+			c.non_negative(name, value);
+		}
+	}
+
+	if let Some(opt) = &score.optional {
+		for (name, value) in [
+			("fast", opt.fast),
+			("slow", opt.slow),
+			("maxCombo", opt.max_combo),
+		] {
+			c.non_negative(name, value);
+		}
+	}
+
+	match score.lamp {
+		USCLamp::PerfectUltimateChain if score.score != USC_MAX_SCORE => c.warning(format!(
+			"Lamp is PERFECT ULTIMATE CHAIN, but score is {}.",
+			score.score
+		)),
+		USCLamp::UltimateChain | USCLamp::PerfectUltimateChain if miss.is_some_and(|e| e > 0) => c
+			.warning(format!(
+				"Lamp is an ULTIMATE CHAIN, but there are {} misses.",
+				miss.unwrap_or_default()
+			)),
+		_ => {}
+	}
+}
+
+fn check_scores<S: DeserializeOwned>(
+	scores: &[Value],
+	problems: &mut Vec<Problem>,
+	check: impl Fn(&S, &mut ScoreChecker),
+) {
+	for (index, value) in scores.iter().enumerate() {
+		let identifier = value["identifier"].as_str().unwrap_or("?");
+
+		let mut checker = ScoreChecker {
+			index,
+			identifier,
+			problems: &mut *problems,
+		};
+
+		match S::deserialize(value) {
+			Ok(score) => check(&score, &mut checker),
+			Err(err) => checker.problem(format!("Invalid score: {err}.")),
+		}
+	}
+}
+
+/// `n` of `noun`, with the right plural.
+pub fn count(n: usize, noun: &str) -> String {
+	match n {
+		1 => format!("1 {noun}"),
+		n => format!("{n} {noun}s"),
+	}
+}
+
+/// Everything wrong with `bm`. Tachi should accept it if none of these are errors.
+pub fn validate_batch_manual(bm: &Value) -> Vec<Problem> {
+	let mut problems = vec![];
+
+	let general = |message: String| Problem {
+		index: None,
+		identifier: None,
+		message,
+		severity: Severity::Error,
+	};
+
+	let meta = match BatchManualMeta::deserialize(&bm["meta"]) {
+		Ok(meta) => meta,
+		Err(err) => return vec![general(format!("Invalid meta: {err}."))],
+	};
+
+	let Some(scores) = bm["scores"].as_array() else {
+		return vec![general("scores must be an array.".into())];
+	};
+
+	match (meta.game.as_str(), meta.playtype.as_str()) {
+		("bms", "7K" | "14K") => check_scores(scores, &mut problems, check_bms),
+		("pms", "Controller" | "Keyboard") => check_scores(scores, &mut problems, check_pms),
+		("usc", "Controller" | "Keyboard") => check_scores(scores, &mut problems, check_usc),
+		(game, playtype) => problems.push(general(format!(
+			"Unsupported game/playtype {game} {playtype}."
+		))),
+	}
+
+	problems
+}
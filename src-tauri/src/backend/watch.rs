@@ -0,0 +1,161 @@
+//! Watch mode. Sits in the background and imports new scores once the game has stopped
+//! writing to its score database for a while.
+
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, Instant, SystemTime},
+};
+
+use parking_lot::RwLock;
+
+use super::{
+	auth::check_can_import,
+	batch_manual::find_batch_manuals,
+	checkpoint::Checkpoints,
+	client::TachiClient,
+	config::TISConfig,
+	courses::load_courses,
+	jobs::{JobHandle, JobKind},
+	log::Logger,
+	queue,
+	source::{AnySource, ConvertContext, SOURCES},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// When `path` was last written to. Games with a WAL only touch the `-wal` file until a
+/// checkpoint, so that counts too.
+fn last_modified(path: &Path) -> Option<SystemTime> {
+	let mut wal = path.as_os_str().to_owned();
+	wal.push("-wal");
+
+	[path, Path::new(&wal)]
+		.into_iter()
+		.filter_map(|e| fs::metadata(e).and_then(|e| e.modified()).ok())
+		.max()
+}
+
+/// Convert everything newer than the last import from `source` and upload it.
+async fn auto_import(
+	source: &'static dyn AnySource,
+	opts: serde_json::Value,
+	config: &TISConfig,
+	log: &Logger,
+) -> anyhow::Result<()> {
+	let client = TachiClient::from_config(config, None)?;
+
+	let mut ctx = ConvertContext::new(log.clone());
+	ctx.since = Checkpoints::load().for_source(&client.server().name, source.id());
+	// The game is most likely still open, so never read its databases directly.
+	ctx.snapshot = true;
+	ctx.courses = load_courses();
+
+	let converted = tokio::task::spawn_blocking(move || source.convert_json(opts, &ctx)).await??;
+
+	let manuals: Vec<_> = find_batch_manuals(converted["output"].clone())
+		.into_iter()
+		.filter(|bm| {
+			bm["scores"].as_array().is_some_and(|e| !e.is_empty())
+				|| bm["classes"].as_object().is_some_and(|e| !e.is_empty())
+		})
+		.collect();
+
+	if manuals.is_empty() {
+		log.info(format!("No new {} scores to import.", source.id()));
+		return Ok(());
+	}
+
+	check_can_import(&client).await?;
+
+	for bm in manuals {
+		let item = queue::enqueue(Some(source.id()), &client.server().name, &bm)?;
+		queue::upload(
+			&client,
+			item.id,
+			false,
+			&JobHandle::detached(JobKind::Import),
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+/// Watch the score database of every configured source, and import from it once nothing
+/// has been written for `settle`.
+///
+/// Never returns. Drop or abort the future to stop watching.
+pub async fn watch(config: Arc<RwLock<TISConfig>>, settle: Duration, log: Logger) {
+	log.info(format!(
+		"Watching for new scores. They'll be imported once nothing has been played for {} minutes.",
+		settle.as_secs() / 60
+	));
+
+	let mut seen: HashMap<PathBuf, SystemTime> = HashMap::new();
+	let mut changed_at: HashMap<PathBuf, Instant> = HashMap::new();
+	let mut watching: Option<Vec<PathBuf>> = None;
+
+	loop {
+		let mut config = config.read().clone();
+		let mut targets = vec![];
+
+		for source in SOURCES.iter().copied() {
+			if let Some((path, opts)) = source.watch_target(&mut config) {
+				targets.push((source, path, opts));
+			}
+		}
+
+		let paths: Vec<_> = targets.iter().map(|(_, path, _)| path.clone()).collect();
+		if watching.as_ref() != Some(&paths) {
+			for path in &paths {
+				log.info(format!("Watching {path:?}."));
+			}
+			if paths.is_empty() {
+				log.warn("No score databases set up to watch. Convert one by hand first.".into());
+			}
+			watching = Some(paths);
+		}
+
+		for (source, path, opts) in targets {
+			let Some(modified) = last_modified(&path) else {
+				continue;
+			};
+
+			// The first look at a file is just to see where it's at, not a change.
+			if seen
+				.insert(path.clone(), modified)
+				.is_some_and(|prev| prev != modified)
+			{
+				if !changed_at.contains_key(&path) {
+					log.info(format!(
+						"{path:?} changed, waiting for the session to end..."
+					));
+				}
+				changed_at.insert(path.clone(), Instant::now());
+			}
+
+			if changed_at
+				.get(&path)
+				.is_some_and(|at| at.elapsed() >= settle)
+			{
+				changed_at.remove(&path);
+
+				log.info(format!(
+					"No writes to {path:?} for a while, importing new scores."
+				));
+
+				if let Err(err) = auto_import(source, opts, &config, &log).await {
+					log.error(format!(
+						"Automatic import from {} failed: {err}",
+						source.id()
+					));
+				}
+			}
+		}
+
+		tokio::time::sleep(POLL_INTERVAL).await;
+	}
+}
@@ -0,0 +1,502 @@
+//! Headless TIS. Converts local databases and imports them without ever opening a window.
+//!
+//! Anything not passed as a flag is read from `tis-config.json`, the same file the GUI uses.
+
+use std::{
+	fs,
+	io::{self, Read, Write},
+	path::{Path, PathBuf},
+	process::ExitCode,
+	sync::Arc,
+	time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use import_scripts_lib::backend::{
+	auth::{check_auth, check_can_import},
+	batch_manual::find_batch_manuals,
+	beatoraja::{Beatoraja, LNMode},
+	bms::PMSPlaytype,
+	checkpoint::Checkpoints,
+	client::TachiClient,
+	config::{default_ln_modes, BeatorajaConfig, LR2Config, TISConfig, USCConfig},
+	courses::load_courses,
+	history::{self, HistoryQuery},
+	import::revert_import,
+	jobs::{JobHandle, JobKind},
+	log::{self, FileSink, Logger, SerializableLevel, StderrSink},
+	lr2::LR2,
+	report::Converted,
+	source::{ConvertContext, ScoreSource},
+	upload::import_in_chunks,
+	usc::{USCPlaytype, USC},
+	validate::{count, validate_batch_manual},
+	watch::watch,
+};
+
+#[derive(Debug, Parser)]
+#[command(
+	name = "tis",
+	version,
+	about = "Tachi Import Scripts, without the window."
+)]
+struct Cli {
+	/// Config file to read defaults (paths, auth token, server) from.
+	#[arg(long, global = true, default_value = "tis-config.json")]
+	config: PathBuf,
+
+	/// Log debug messages too.
+	#[arg(long, short, global = true)]
+	verbose: bool,
+
+	/// Write logs to this file instead of stderr.
+	#[arg(long, global = true)]
+	log_file: Option<PathBuf>,
+
+	/// When converting, also write a report of every skipped score to this file.
+	#[arg(long, global = true)]
+	report: Option<PathBuf>,
+
+	/// Convert every score, not just the ones newer than the last import.
+	#[arg(long, global = true)]
+	full_resync: bool,
+
+	/// Name of the server profile to import to, instead of the active one.
+	#[arg(long, global = true)]
+	server: Option<String>,
+
+	/// Base URL to use for the server, instead of the one in the config file.
+	#[arg(long, global = true)]
+	base_url: Option<String>,
+
+	/// Give up on an import if it hasn't finished after this many minutes.
+	#[arg(long, global = true)]
+	timeout_minutes: Option<u64>,
+
+	/// Copy databases to memory before converting, so the game can be left running.
+	#[arg(long, global = true)]
+	snapshot: bool,
+
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Convert an LR2 score database and print the batch-manual JSON.
+	Lr2 {
+		/// Score database (<username>.db).
+		#[arg(long)]
+		score_path: Option<PathBuf>,
+		/// Chart database (song.db).
+		#[arg(long)]
+		chart_path: Option<PathBuf>,
+		/// What PMS scores were played on (controller, keyboard). They're skipped if this isn't
+		/// given here or in the config file.
+		#[arg(long, value_parser = parse_pms_playtype)]
+		pms_playtype: Option<PMSPlaytype>,
+		/// An .lr2crs course file, or a folder of them, to find dan courses in.
+		#[arg(long)]
+		course_path: Option<PathBuf>,
+	},
+	/// Convert a beatoraja score database and print the batch-manual JSON.
+	Beatoraja {
+		/// Score database (player/<player>/score.db).
+		#[arg(long)]
+		score_path: Option<PathBuf>,
+		/// Chart database (songdata.db).
+		#[arg(long)]
+		chart_path: Option<PathBuf>,
+		/// Include every play from the play log, not just each chart's best.
+		#[arg(long)]
+		play_history: bool,
+		/// With --play-history and no scoredatalog.db, send each new best from the scorelog
+		/// table instead. These are bests as they stood after each play, not the plays.
+		#[arg(long)]
+		scorelog_bests: bool,
+		/// LN modes (ln, cn, hcn) to send scores from, for charts that let you pick. Defaults
+		/// to the config file's, or just ln.
+		#[arg(long, value_parser = parse_ln_mode, value_delimiter = ',')]
+		ln_modes: Vec<LNMode>,
+		/// What PMS scores were played on (controller, keyboard). They're skipped if this isn't
+		/// given here or in the config file.
+		#[arg(long, value_parser = parse_pms_playtype)]
+		pms_playtype: Option<PMSPlaytype>,
+	},
+	/// Convert a USC maps.db and print the batch-manual JSON.
+	Usc {
+		/// Score database (maps.db).
+		#[arg(long)]
+		db_path: Option<PathBuf>,
+		/// The input device these scores were set on.
+		#[arg(long, value_parser = parse_usc_playtype)]
+		playtype: Option<USCPlaytype>,
+	},
+	/// Upload batch-manual JSON to Tachi and print a summary of the import.
+	///
+	/// Accepts batch manuals or the output of the convert subcommands. Reads stdin if no files
+	/// are given.
+	Import {
+		files: Vec<PathBuf>,
+		/// API token to use instead of the one in the config file.
+		#[arg(long)]
+		token: Option<String>,
+		/// The source (lr2, beatoraja, usc) these scores were converted from. If given, later
+		/// conversions from it will only include scores newer than this import.
+		#[arg(long)]
+		source: Option<String>,
+	},
+	/// Check batch-manual JSON for anything Tachi would reject, without uploading it.
+	///
+	/// Prints the problems found as JSON. Import does this on its own before uploading.
+	Validate { files: Vec<PathBuf> },
+	/// Check the API token, printing who it belongs to and what it's allowed to do as JSON.
+	CheckAuth {
+		/// API token to use instead of the one in the config file.
+		#[arg(long)]
+		token: Option<String>,
+	},
+	/// Undo an import, removing the scores and sessions it made on the server.
+	Revert {
+		/// The import's ID, as shown by `tis history`.
+		import_id: String,
+		/// API token to use instead of the one in the config file.
+		#[arg(long)]
+		token: Option<String>,
+	},
+	/// Print past imports as JSON, newest first.
+	History {
+		/// Only imports from this source (lr2, beatoraja, usc).
+		#[arg(long)]
+		source: Option<String>,
+		/// Only imports from the last this many hours.
+		#[arg(long)]
+		hours: Option<i64>,
+		#[arg(long, default_value_t = 20)]
+		limit: usize,
+	},
+	/// Watch the configured beatoraja and USC databases, and import new scores once nothing
+	/// has been written to them for a while. Runs until interrupted.
+	Watch {
+		/// How many minutes of no writes count as the end of a session.
+		#[arg(long, default_value_t = 5)]
+		settle_minutes: u64,
+	},
+}
+
+impl Command {
+	/// The source a convert subcommand reads from.
+	fn source_id(&self) -> Option<&'static str> {
+		match self {
+			Command::Lr2 { .. } => Some(LR2::ID),
+			Command::Beatoraja { .. } => Some(Beatoraja::ID),
+			Command::Usc { .. } => Some(USC::ID),
+			Command::Import { .. }
+			| Command::Validate { .. }
+			| Command::CheckAuth { .. }
+			| Command::Revert { .. }
+			| Command::History { .. }
+			| Command::Watch { .. } => None,
+		}
+	}
+}
+
+fn parse_ln_mode(s: &str) -> Result<LNMode, String> {
+	match s.to_lowercase().as_str() {
+		"ln" => Ok(LNMode::LongNote),
+		"cn" => Ok(LNMode::ChargeNote),
+		"hcn" => Ok(LNMode::HellChargeNote),
+		_ => Err(format!("expected ln, cn or hcn, got {s}")),
+	}
+}
+
+fn parse_pms_playtype(s: &str) -> Result<PMSPlaytype, String> {
+	match s.to_lowercase().as_str() {
+		"controller" => Ok(PMSPlaytype::Controller),
+		"keyboard" => Ok(PMSPlaytype::Keyboard),
+		_ => Err(format!("expected Controller or Keyboard, got {s}")),
+	}
+}
+
+fn parse_usc_playtype(s: &str) -> Result<USCPlaytype, String> {
+	match s.to_lowercase().as_str() {
+		"controller" => Ok(USCPlaytype::Controller),
+		"keyboard" => Ok(USCPlaytype::Keyboard),
+		_ => Err(format!("expected Controller or Keyboard, got {s}")),
+	}
+}
+
+fn print_json(value: &impl Serialize) -> anyhow::Result<()> {
+	writeln!(
+		io::stdout().lock(),
+		"{}",
+		serde_json::to_string_pretty(value)?
+	)?;
+
+	Ok(())
+}
+
+fn print_converted(
+	converted: Converted<impl Serialize>,
+	report_path: Option<&Path>,
+) -> anyhow::Result<()> {
+	if let Some(path) = report_path {
+		fs::write(path, serde_json::to_string_pretty(&converted.report)?)
+			.map_err(|e| anyhow!("Couldn't write report to {path:?}: {e}"))?;
+	}
+
+	print_json(&converted.output)
+}
+
+fn read_inputs(files: &[PathBuf]) -> anyhow::Result<Vec<serde_json::Value>> {
+	let mut inputs = vec![];
+
+	if files.is_empty() {
+		let mut buf = String::new();
+		io::stdin().read_to_string(&mut buf)?;
+		inputs.push(serde_json::from_str(&buf)?);
+	}
+
+	for file in files {
+		let content = fs::read(file).map_err(|e| anyhow!("Couldn't read {file:?}: {e}"))?;
+		inputs.push(
+			serde_json::from_slice(&content)
+				.map_err(|e| anyhow!("Invalid JSON in {file:?}: {e}"))?,
+		);
+	}
+
+	Ok(inputs.into_iter().flat_map(find_batch_manuals).collect())
+}
+
+/// A client for the active server, using `token` instead of its configured one if given.
+fn client(mut config: TISConfig, token: Option<String>) -> anyhow::Result<TachiClient> {
+	if let (Some(token), Some(server)) = (token, config.active_server_mut()) {
+		server.auth_token = Some(token);
+	}
+
+	if config
+		.active_server()
+		.is_some_and(|e| e.auth_token.is_none())
+	{
+		bail!("You have no auth token set up. Pass --token or add one to the config.");
+	}
+
+	TachiClient::from_config(&config, None)
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+	let mut config = if cli.config.exists() {
+		TISConfig::load_from(&cli.config)
+	} else {
+		TISConfig::default()
+	};
+	config.finish_migration(&cli.config);
+
+	if let Some(name) = cli.server {
+		config.pick_server(Some(&name))?;
+		config.active_server = name;
+	}
+	if let (Some(base_url), Some(server)) = (cli.base_url, config.active_server_mut()) {
+		server.base_url = base_url;
+	}
+	if let Some(minutes) = cli.timeout_minutes {
+		config.import_timeout_minutes = minutes;
+	}
+
+	let mut ctx = ConvertContext::new(log::global());
+	ctx.snapshot = cli.snapshot || config.snapshot_dbs;
+	ctx.courses = load_courses();
+
+	if !cli.full_resync {
+		if let Some(id) = cli.command.source_id() {
+			let server = &config.pick_server(None)?.name;
+			ctx.since = Checkpoints::load().for_source(server, id);
+		}
+	}
+
+	match cli.command {
+		Command::Lr2 {
+			score_path,
+			chart_path,
+			pms_playtype,
+			course_path,
+		} => {
+			let saved = config.lr2.as_ref();
+			let (Some(score_path), Some(chart_path)) = (
+				score_path.or_else(|| saved.map(|e| e.score_path.clone())),
+				chart_path.or_else(|| saved.map(|e| e.chart_path.clone())),
+			) else {
+				bail!("No LR2 database configured. Pass --score-path and --chart-path.");
+			};
+
+			print_converted(
+				LR2::convert(
+					&LR2Config {
+						score_path,
+						chart_path,
+						pms_playtype: pms_playtype.or_else(|| saved.and_then(|e| e.pms_playtype)),
+						course_path: course_path
+							.or_else(|| saved.and_then(|e| e.course_path.clone())),
+					},
+					&ctx,
+				)?,
+				cli.report.as_deref(),
+			)
+		}
+		Command::Beatoraja {
+			score_path,
+			chart_path,
+			play_history,
+			scorelog_bests,
+			ln_modes,
+			pms_playtype,
+		} => {
+			let saved = config.beatoraja_db.as_ref();
+			let (Some(score_path), Some(chart_path)) = (
+				score_path.or_else(|| saved.map(|e| e.score_path.clone())),
+				chart_path.or_else(|| saved.map(|e| e.chart_path.clone())),
+			) else {
+				bail!("No beatoraja database configured. Pass --score-path and --chart-path.");
+			};
+
+			print_converted(
+				Beatoraja::convert(
+					&BeatorajaConfig {
+						score_path,
+						chart_path,
+						play_history: play_history || saved.is_some_and(|e| e.play_history),
+						scorelog_bests: scorelog_bests || saved.is_some_and(|e| e.scorelog_bests),
+						ln_modes: match (ln_modes.is_empty(), saved) {
+							(false, _) => ln_modes,
+							(true, Some(saved)) => saved.ln_modes.clone(),
+							(true, None) => default_ln_modes(),
+						},
+						pms_playtype: pms_playtype.or_else(|| saved.and_then(|e| e.pms_playtype)),
+					},
+					&ctx,
+				)?,
+				cli.report.as_deref(),
+			)
+		}
+		Command::Usc { db_path, playtype } => {
+			let saved = config.usc_db.as_ref();
+			let (Some(db_path), Some(playtype)) = (
+				db_path.or_else(|| saved.map(|e| e.db_path.clone())),
+				playtype.or_else(|| saved.map(|e| e.playtype)),
+			) else {
+				bail!("No USC database configured. Pass --db-path and --playtype.");
+			};
+
+			print_converted(
+				USC::convert(&USCConfig { db_path, playtype }, &ctx)?,
+				cli.report.as_deref(),
+			)
+		}
+		Command::Import {
+			files,
+			token,
+			source,
+		} => {
+			let client = client(config, token)?;
+			check_can_import(&client).await?;
+
+			let mut documents = vec![];
+
+			for bm in read_inputs(&files)? {
+				documents.extend(
+					import_in_chunks(
+						&client,
+						&bm,
+						source.as_deref(),
+						true,
+						&JobHandle::detached(JobKind::Import),
+					)
+					.await?,
+				);
+
+				if let Some(source) = &source {
+					let mut checkpoints = Checkpoints::load();
+					checkpoints.record_import(&client.server().name, source, &bm);
+					checkpoints.save();
+				}
+			}
+
+			print_json(&documents)
+		}
+		Command::Validate { files } => {
+			let problems: Vec<_> = read_inputs(&files)?
+				.iter()
+				.flat_map(validate_batch_manual)
+				.collect();
+
+			print_json(&problems)?;
+
+			let errors = problems.iter().filter(|e| e.is_error()).count();
+			if errors > 0 {
+				bail!("Found {}.", count(errors, "error"));
+			}
+
+			Ok(())
+		}
+		Command::CheckAuth { token } => print_json(&check_auth(&client(config, token)?).await?),
+		Command::Revert { import_id, token } => {
+			revert_import(&client(config, token)?, &import_id).await
+		}
+		Command::History {
+			source,
+			hours,
+			limit,
+		} => print_json(&history::query(&HistoryQuery {
+			source,
+			since: hours.map(|e| Utc::now().timestamp_millis() - e * 60 * 60 * 1000),
+			limit: Some(limit),
+			..Default::default()
+		})),
+		Command::Watch { settle_minutes } => {
+			watch(
+				Arc::new(RwLock::new(config)),
+				Duration::from_secs(settle_minutes * 60),
+				log::global(),
+			)
+			.await;
+
+			Ok(())
+		}
+	}
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+	let cli = Cli::parse();
+
+	// stdout is reserved for JSON output, so logs have to go somewhere else.
+	let min_level = if cli.verbose {
+		SerializableLevel::Debug
+	} else {
+		SerializableLevel::Info
+	};
+
+	match &cli.log_file {
+		Some(path) => match FileSink::open(path, min_level) {
+			Ok(sink) => log::set_global(Logger::new(sink)),
+			Err(err) => {
+				eprintln!("error: Couldn't open log file {path:?}: {err}");
+				return ExitCode::FAILURE;
+			}
+		},
+		None => log::set_global(Logger::new(StderrSink { min_level })),
+	}
+
+	match run(cli).await {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(err) => {
+			eprintln!("error: {err}");
+			ExitCode::FAILURE
+		}
+	}
+}
@@ -1,29 +1,39 @@
-use serde::{Deserialize, Serialize};
 use std::{
 	fs,
-	sync::{Arc, OnceLock},
+	path::{Path, PathBuf},
+	sync::Arc,
 	time::Duration,
 };
 use tracing::Level;
 
-use chrono::Utc;
-use parking_lot::RwLock;
-use tauri::{AppHandle, Manager};
+use anyhow::anyhow;
+use parking_lot::{Mutex, RwLock};
+use tauri::{async_runtime::JoinHandle, Manager};
+use tauri_plugin_opener::OpenerExt;
 
 use self::backend::{
-	batch_manual::USCBatchManual,
-	beatoraja::convert_beatoraja_db,
-	bms::BMSConvertResults,
-	config::{BeatorajaConfig, LR2Config, TISConfig, USCConfig},
-	log::{self, SerializableLevel},
-	lr2::convert_lr2_db,
-	usc::convert_usc_db,
+	auth::{self, AuthStatus},
+	checkpoint::Checkpoints,
+	client::TachiClient,
+	config::{ServerConfig, TISConfig},
+	courses::load_courses,
+	history::{self, HistoryQuery, ImportRecord},
+	import::{self, ImportDocument},
+	jobs::{JobId, JobKind, Jobs},
+	log::{self, Logger, SerializableLevel, TauriSink},
+	oauth,
+	queue::{self, QueueItem, QueueItemId},
+	report::ConversionReport,
+	source::{find_source, ConvertContext},
+	watch::watch,
 };
 
-mod backend;
+pub mod backend;
 
 struct State {
 	pub config: Arc<RwLock<TISConfig>>,
+	pub watcher: Mutex<Option<JoinHandle<()>>>,
+	pub jobs: Jobs,
 }
 
 #[tauri::command]
@@ -31,203 +41,263 @@ fn config(state: tauri::State<State>) -> TISConfig {
 	state.config.read().clone()
 }
 
+/// Set the token for the active server.
 #[tauri::command]
 fn update_api_token(state: tauri::State<State>, token: String) {
-	state.config.write().auth_token = Some(token);
+	if let Some(server) = state.config.write().active_server_mut() {
+		server.auth_token = Some(token);
+	}
 	state.config.read().save();
 }
 
+/// Log in to a server (the active one by default) through the browser, and keep the token.
 #[tauri::command]
-fn lr2_convert(state: tauri::State<State>, opts: LR2Config) -> Result<BMSConvertResults, String> {
-	let conv = convert_lr2_db(&opts).map_err(|e| e.to_string())?;
+async fn oauth_login(
+	app: tauri::AppHandle,
+	state: tauri::State<'_, State>,
+	server: Option<String>,
+) -> Result<String, String> {
+	let profile = state
+		.config
+		.read()
+		.pick_server(server.as_deref())
+		.map_err(|e| e.to_string())?
+		.clone();
+
+	let token = oauth::login(
+		&profile,
+		|url| {
+			app.opener()
+				.open_url(url, None::<&str>)
+				.map_err(|e| anyhow!("Couldn't open your browser: {e}"))
+		},
+		oauth::LOGIN_TIMEOUT,
+	)
+	.await
+	.map_err(|e| e.to_string())?;
+
+	if let Some(server) = state
+		.config
+		.write()
+		.servers
+		.iter_mut()
+		.find(|e| e.name == profile.name)
+	{
+		server.auth_token = Some(token.clone());
+	}
+	state.config.read().save();
 
-	log::info("Conversion complete".to_string());
+	Ok(token)
+}
 
-	state.config.write().lr2 = Some(opts.clone());
+#[tauri::command]
+fn set_active_server(state: tauri::State<State>, name: String) -> Result<(), String> {
+	state
+		.config
+		.read()
+		.pick_server(Some(&name))
+		.map_err(|e| e.to_string())?;
+
+	state.config.write().active_server = name;
 	state.config.read().save();
 
-	Ok(conv)
+	Ok(())
 }
 
+/// Add a server profile, or update the one with the same name.
 #[tauri::command]
-fn beatoraja_convert(
-	state: tauri::State<State>,
-	opts: BeatorajaConfig,
-) -> Result<BMSConvertResults, String> {
-	let conv = convert_beatoraja_db(&opts).map_err(|e| e.to_string())?;
-
-	log::info("Conversion complete".to_string());
+fn save_server(state: tauri::State<State>, server: ServerConfig) {
+	state.config.write().upsert_server(server);
+	state.config.read().save();
+}
 
-	state.config.write().beatoraja_db = Some(opts.clone());
+#[tauri::command]
+fn remove_server(state: tauri::State<State>, name: String) {
+	state.config.write().servers.retain(|e| e.name != name);
 	state.config.read().save();
+}
 
-	Ok(conv)
+#[tauri::command]
+fn update_snapshot_dbs(state: tauri::State<State>, snapshot: bool) {
+	state.config.write().snapshot_dbs = snapshot;
+	state.config.read().save();
 }
 
 #[tauri::command]
-fn usc_convert(state: tauri::State<State>, opts: USCConfig) -> Result<USCBatchManual, String> {
-	let conv = convert_usc_db(&opts).map_err(|e| e.to_string())?;
+async fn convert(
+	state: tauri::State<'_, State>,
+	source: String,
+	opts: serde_json::Value,
+	full_resync: bool,
+	server: Option<String>,
+) -> Result<serde_json::Value, String> {
+	let Some(source) = find_source(&source) else {
+		return Err(format!("Unknown source '{source}'."));
+	};
+
+	let mut ctx = ConvertContext::new(log::global());
+	ctx.snapshot = state.config.read().snapshot_dbs;
+	ctx.courses = load_courses();
+
+	if !full_resync {
+		// Only scores the server they're going to doesn't have yet.
+		let server = state
+			.config
+			.read()
+			.pick_server(server.as_deref())
+			.map(|e| e.name.clone())
+			.map_err(|e| e.to_string())?;
+
+		ctx.since = Checkpoints::load().for_source(&server, source.id());
+	}
+
+	let job = state.jobs.start(JobKind::Convert);
+	ctx.job = job.clone();
+
+	let conv = {
+		let opts = opts.clone();
+		tauri::async_runtime::spawn_blocking(move || source.convert_json(opts, &ctx))
+			.await
+			.map_err(|e| anyhow!("Conversion crashed: {e}"))
+			.and_then(|e| e)
+	};
+	state.jobs.finish(&job, &conv);
+	let conv = conv.map_err(|e| e.to_string())?;
 
 	log::info("Conversion complete".to_string());
 
-	state.config.write().usc_db = Some(opts.clone());
+	source
+		.save_opts(&mut state.config.write(), opts)
+		.map_err(|e| e.to_string())?;
 	state.config.read().save();
 
 	Ok(conv)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ImportResponseBody {
-	url: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ImportResponse {
-	success: bool,
-	description: String,
-	body: Option<ImportResponseBody>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ImportPollProgress {
-	description: String,
-}
+#[tauri::command]
+fn detect_source(path: PathBuf) -> Result<Option<String>, String> {
+	let source = backend::source::detect_source(&path).map_err(|e| e.to_string())?;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "importStatus")]
-enum ImportPollResponseProgress {
-	#[serde(rename = "completed")]
-	Completed { import: serde_json::Value },
-	#[serde(rename = "ongoing")]
-	Ongoing { progress: ImportPollProgress },
+	Ok(source.map(|e| e.id().to_string()))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ImportPollResponseBody {
-	#[serde(flatten)]
-	progress: ImportPollResponseProgress,
-}
+#[tauri::command]
+fn export_report(report: ConversionReport, path: PathBuf) -> Result<(), String> {
+	let json = serde_json::to_string_pretty(&report).expect("must ser");
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ImportPollResponse {
-	success: bool,
-	description: String,
-	body: Option<ImportPollResponseBody>,
+	fs::write(&path, json).map_err(|e| format!("Failed to write report to {path:?}: {e}"))
 }
 
 #[tauri::command]
-async fn import(state: tauri::State<'_, State>, bm: serde_json::Value) -> Result<(), String> {
-	let _ = fs::create_dir_all("batch-manual");
+async fn import(
+	state: tauri::State<'_, State>,
+	source: String,
+	bm: serde_json::Value,
+	server: Option<String>,
+) -> Result<Vec<ImportDocument>, String> {
+	let config = state.config.read().clone();
+	let client = TachiClient::from_config(&config, server.as_deref()).map_err(|e| e.to_string())?;
 
-	log::info("Making import...".into());
+	auth::check_can_import(&client)
+		.await
+		.map_err(|e| e.to_string())?;
 
-	let game = bm
-		.get("meta")
-		.and_then(|e| e.get("game"))
-		.and_then(|e| e.as_str().map(|e| e.to_owned()))
-		.unwrap_or("invalid".to_owned());
-	let playtype = bm
-		.get("meta")
-		.and_then(|e| e.get("playtype"))
-		.and_then(|e| e.as_str().map(|e| e.to_owned()))
-		.unwrap_or("invalid".to_owned());
+	let item =
+		queue::enqueue(Some(&source), &client.server().name, &bm).map_err(|e| e.to_string())?;
 
-	let filename = format!("{}-{game}-{playtype}.json", Utc::now().timestamp_millis(),);
+	let job = state.jobs.start(JobKind::Import);
+	let res = queue::upload(&client, item.id, true, &job).await;
+	state.jobs.finish(&job, &res);
 
-	let _ = fs::write(
-		format!("batch-manual/{filename}"),
-		serde_json::to_string_pretty(&bm).expect("must ser"),
-	);
+	res.map_err(|e| e.to_string())
+}
 
+/// Who the token for a server (the active one by default) belongs to, and what it can do.
+#[tauri::command]
+async fn check_auth(
+	state: tauri::State<'_, State>,
+	server: Option<String>,
+) -> Result<AuthStatus, String> {
 	let config = state.config.read().clone();
+	let client = TachiClient::from_config(&config, server.as_deref()).map_err(|e| e.to_string())?;
 
-	let Some(auth) = config.auth_token else {
-		return Err("You have no auth token set up.".into());
-	};
+	auth::check_auth(&client).await.map_err(|e| e.to_string())
+}
 
-	let client = reqwest::Client::new();
+#[tauri::command]
+async fn revert_import(
+	state: tauri::State<'_, State>,
+	import_id: String,
+	server: Option<String>,
+) -> Result<(), String> {
+	let config = state.config.read().clone();
+	let client = TachiClient::from_config(&config, server.as_deref()).map_err(|e| e.to_string())?;
 
-	let res = client
-		.post(format!(
-			"{}/ir/direct-manual/import",
-			config.server.base_url
-		))
-		.header("Authorization", format!("Bearer {auth}"))
-		.header("Content-Type", "application/json")
-		.header("X-User-Intent", "true")
-		.header("User-Agent", "TIS/2.2.0")
-		.body(serde_json::to_string(&bm).expect("must ser"))
-		.send()
-		.await;
+	import::revert_import(&client, &import_id)
+		.await
+		.map_err(|e| e.to_string())
+}
 
-	let res = res.map_err(|e| format!("Request failed: {e}"))?;
+#[tauri::command]
+fn import_history(query: HistoryQuery) -> Vec<ImportRecord> {
+	history::query(&query)
+}
 
-	log::info("Request OK...".into());
+#[tauri::command]
+fn list_queue() -> Vec<QueueItem> {
+	queue::list()
+}
 
-	let json_body: ImportResponse = res
-		.json()
-		.await
-		.map_err(|_| "Invalid response from server.".to_string())?;
+#[tauri::command]
+async fn retry_queue_item(
+	state: tauri::State<'_, State>,
+	id: QueueItemId,
+) -> Result<Vec<ImportDocument>, String> {
+	let config = state.config.read().clone();
 
-	let Some(body) = json_body.body else {
-		return Err(format!(
-			"Failed to submit scores. {}",
-			json_body.description
-		));
+	let Some(item) = queue::list().into_iter().find(|e| e.id == id) else {
+		return Err(format!("There's no queued import {id}."));
 	};
 
-	let mut last_desc = String::new();
-
-	let doc = loop {
-		let res = client
-			.get(&body.url)
-			.send()
-			.await
-			.map_err(|e| format!("Request failed: {e}"))?;
+	// Queued before there were server profiles.
+	let server = Some(item.server.as_str()).filter(|e| !e.is_empty());
+	let client = TachiClient::from_config(&config, server).map_err(|e| e.to_string())?;
 
-		let json: ImportPollResponse = res
-			.json()
-			.await
-			.map_err(|e| format!("Invalid response from server ({e:?})."))?;
+	auth::check_can_import(&client)
+		.await
+		.map_err(|e| e.to_string())?;
 
-		match json.body {
-			Some(body) => match body.progress {
-				ImportPollResponseProgress::Completed { import } => {
-					break import;
-				}
-				ImportPollResponseProgress::Ongoing { progress } => {
-					if progress.description != last_desc {
-						last_desc = progress.description;
-						log::info(last_desc.clone());
-					}
+	let job = state.jobs.start(JobKind::Import);
+	let res = queue::upload(&client, id, true, &job).await;
+	state.jobs.finish(&job, &res);
 
-					tokio::time::sleep(Duration::from_secs(1)).await;
-				}
-			},
-			None => {
-				log::error(format!("Failed to process import: {}", json.description));
-				return Err(format!("Failed to process import: {}", json.description));
-			}
-		}
-	};
-	log::info(format!(
-		"Successfully imported scores for {game} ({playtype})"
-	));
+	res.map_err(|e| e.to_string())
+}
 
-	let score_count = match doc.get("scoreIDs") {
-		Some(serde_json::Value::Array(v)) => v.len(),
-		_ => 0,
-	};
+#[tauri::command]
+fn cancel_job(state: tauri::State<State>, id: JobId) -> bool {
+	state.jobs.cancel(id)
+}
 
-	let failed = match doc.get("errors") {
-		Some(serde_json::Value::Array(v)) => v.len(),
-		_ => 0,
-	};
+#[tauri::command]
+fn start_watch(state: tauri::State<State>, settle_minutes: u64) {
+	let task = tauri::async_runtime::spawn(watch(
+		state.config.clone(),
+		Duration::from_secs(settle_minutes * 60),
+		log::global(),
+	));
 
-	log::info(format!("New Scores: {score_count} | Failed {failed}",));
+	if let Some(old) = state.watcher.lock().replace(task) {
+		old.abort();
+	}
+}
 
-	Ok(())
+#[tauri::command]
+fn stop_watch(state: tauri::State<State>) {
+	if let Some(task) = state.watcher.lock().take() {
+		task.abort();
+		log::info("Stopped watching for new scores.".into());
+	}
 }
 
 #[tauri::command]
@@ -240,8 +310,6 @@ fn log(level: SerializableLevel, content: String) {
 	}
 }
 
-pub static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
-
 pub fn run() {
 	tracing_subscriber::fmt::fmt()
 		.with_max_level(Level::DEBUG)
@@ -251,8 +319,24 @@ pub fn run() {
 	let app = tauri::Builder::default()
 		.plugin(tauri_plugin_dialog::init())
 		.setup(|app| {
+			log::set_global(Logger::new(TauriSink(app.handle().clone())));
+
+			let mut config = TISConfig::load();
+			config.finish_migration(Path::new(TISConfig::PATH));
+
+			// Anything that didn't make it to a server last time.
+			for server in &config.servers {
+				if let Ok(client) = TachiClient::from_config(&config, Some(&server.name)) {
+					tauri::async_runtime::spawn(async move {
+						queue::upload_pending(&client).await;
+					});
+				}
+			}
+
 			app.manage(State {
-				config: Arc::new(RwLock::new(TISConfig::load())),
+				config: Arc::new(RwLock::new(config)),
+				watcher: Mutex::new(None),
+				jobs: Jobs::new(TauriSink(app.handle().clone())),
 			});
 
 			Ok(())
@@ -261,16 +345,27 @@ pub fn run() {
 		.invoke_handler(tauri::generate_handler![
 			config,
 			update_api_token,
-			usc_convert,
-			lr2_convert,
-			beatoraja_convert,
+			check_auth,
+			oauth_login,
+			set_active_server,
+			save_server,
+			remove_server,
+			update_snapshot_dbs,
+			convert,
+			detect_source,
+			export_report,
 			import,
+			import_history,
+			revert_import,
+			list_queue,
+			retry_queue_item,
+			cancel_job,
+			start_watch,
+			stop_watch,
 			log
 		])
 		.build(tauri::generate_context!())
 		.expect("failed to boot");
 
-	APP_HANDLE.set(app.handle().clone()).expect("must work");
-
 	app.run(|_, _| {});
 }
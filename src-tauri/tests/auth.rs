@@ -0,0 +1,109 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use import_scripts_lib::backend::{
+	auth::{check_auth, check_can_import},
+	client::{ClientOptions, TachiClient},
+	config::ServerConfig,
+};
+use serde_json::json;
+
+use common::{me, status, MockServer, Response, ME, STATUS};
+
+fn client(server: &MockServer) -> TachiClient {
+	let config = ServerConfig {
+		base_url: server.base_url().into(),
+		..Default::default()
+	};
+
+	TachiClient::new(
+		&config,
+		"token",
+		ClientOptions {
+			initial_backoff: Duration::from_millis(10),
+			..Default::default()
+		},
+	)
+	.unwrap()
+}
+
+#[tokio::test]
+async fn reports_user_and_permissions() {
+	let server = MockServer::start();
+	server.on(
+		"GET",
+		STATUS,
+		vec![status(Some(1), &["submit_score", "customise_profile"])],
+	);
+	server.on("GET", ME, vec![me()]);
+
+	let status = check_auth(&client(&server)).await.unwrap();
+
+	assert_eq!(status.username, "zkldi");
+	assert_eq!(status.user_id, 1);
+	assert_eq!(status.server, "Bokutachi");
+	assert!(status.can_submit);
+	assert_eq!(
+		server.requests_to(STATUS)[0].header("Authorization"),
+		Some("Bearer token")
+	);
+
+	check_can_import(&client(&server)).await.unwrap();
+}
+
+#[tokio::test]
+async fn missing_submit_score_blocks_imports() {
+	let server = MockServer::start();
+	server.on("GET", STATUS, vec![status(Some(1), &["customise_profile"])]);
+	server.on("GET", ME, vec![me()]);
+
+	let status = check_auth(&client(&server)).await.unwrap();
+	assert!(!status.can_submit);
+
+	let err = check_can_import(&client(&server)).await.unwrap_err();
+	assert!(err.to_string().contains("submit_score"), "{err}");
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_reported() {
+	let server = MockServer::start();
+	server.on("GET", STATUS, vec![status(None, &[])]);
+
+	let err = check_auth(&client(&server)).await.unwrap_err();
+	assert!(
+		err.to_string().contains("invalid or has been revoked"),
+		"{err}"
+	);
+	assert!(server.requests_to(ME).is_empty());
+
+	let server = MockServer::start();
+	server.on(
+		"GET",
+		STATUS,
+		vec![Response::json(
+			401,
+			json!({ "success": false, "description": "The provided API token does not correspond with any key in the database." }),
+		)],
+	);
+
+	let err = check_can_import(&client(&server)).await.unwrap_err();
+	assert!(
+		err.to_string().contains("invalid or has been revoked"),
+		"{err}"
+	);
+}
+
+#[tokio::test]
+async fn imports_can_still_be_queued_while_offline() {
+	let server = ServerConfig {
+		base_url: "http://127.0.0.1:1".into(),
+		..Default::default()
+	};
+	// Imports retry for a while, but the check before them shouldn't.
+	let client = TachiClient::new(&server, "token", ClientOptions::default()).unwrap();
+
+	let start = Instant::now();
+	check_can_import(&client).await.unwrap();
+	assert!(start.elapsed() < Duration::from_secs(1));
+}
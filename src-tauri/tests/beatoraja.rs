@@ -0,0 +1,470 @@
+use std::{
+	env, fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use import_scripts_lib::backend::{
+	beatoraja::{Beatoraja, LNMode},
+	bms::PMSPlaytype,
+	config::{default_ln_modes, BeatorajaConfig},
+	courses::{bundled_courses, known_classes, Course, DanClass},
+	log::{Logger, MemorySink, SerializableLevel, StderrSink},
+	report::{Converted, SkipReason},
+	source::{ConvertContext, ScoreSource},
+};
+
+const SHA256: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+const SCORE_COLUMNS: &str = "
+	sha256 TEXT, mode INTEGER, clear INTEGER, date INTEGER,
+	epg INTEGER, lpg INTEGER, egr INTEGER, lgr INTEGER, egd INTEGER, lgd INTEGER,
+	ebd INTEGER, lbd INTEGER, epr INTEGER, lpr INTEGER, ems INTEGER, lms INTEGER,
+	combo INTEGER, minbp INTEGER, random INTEGER";
+
+/// A player folder with one 7K chart, a best of EX 230 and two new bests in `scorelog`.
+fn player_dir(name: &str) -> PathBuf {
+	let dir = env::temp_dir().join(format!("tis-beatoraja-{name}-{}", std::process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+
+	Connection::open(dir.join("songdata.db"))
+		.unwrap()
+		.execute_batch(&format!(
+			"CREATE TABLE song (sha256 TEXT, title TEXT, subtitle TEXT, mode INTEGER, feature INTEGER);
+			INSERT INTO song VALUES ('{SHA256}', 'Song', '', 7, 0);"
+		))
+		.unwrap();
+
+	Connection::open(dir.join("score.db"))
+		.unwrap()
+		.execute_batch(&format!(
+			"CREATE TABLE score ({SCORE_COLUMNS});
+			INSERT INTO score VALUES ('{SHA256}', 0, 6, 1600000200, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 0);
+			CREATE TABLE scorelog (
+				sha256 TEXT, mode INTEGER, clear INTEGER, oldclear INTEGER, score INTEGER,
+				oldscore INTEGER, combo INTEGER, oldcombo INTEGER, minbp INTEGER, oldminbp INTEGER,
+				date INTEGER
+			);
+			INSERT INTO scorelog VALUES ('{SHA256}', 0, 4, 0, 180, 0, 80, 0, 20, 0, 1600000100);
+			INSERT INTO scorelog VALUES ('{SHA256}', 0, 6, 4, 230, 180, 130, 80, 0, 20, 1600000200);"
+		))
+		.unwrap();
+
+	dir
+}
+
+fn convert_with(config: &BeatorajaConfig) -> Converted<Value> {
+	convert_with_courses(config, vec![])
+}
+
+fn convert_with_courses(config: &BeatorajaConfig, courses: Vec<Course>) -> Converted<Value> {
+	let mut ctx = ConvertContext::new(Logger::new(StderrSink {
+		min_level: SerializableLevel::Error,
+	}));
+	ctx.courses = courses;
+
+	let converted = Beatoraja::convert(config, &ctx).unwrap();
+
+	Converted {
+		output: serde_json::to_value(converted.output).unwrap(),
+		report: converted.report,
+	}
+}
+
+fn convert(dir: &Path, play_history: bool) -> Vec<Value> {
+	let converted = convert_with(&BeatorajaConfig {
+		score_path: dir.join("score.db"),
+		chart_path: dir.join("songdata.db"),
+		play_history,
+		scorelog_bests: false,
+		ln_modes: default_ln_modes(),
+		pms_playtype: None,
+	});
+
+	converted.output["k7"]["scores"].as_array().unwrap().clone()
+}
+
+#[test]
+fn bests_only_by_default() {
+	let dir = player_dir("bests");
+	let scores = convert(&dir, false);
+
+	assert_eq!(scores.len(), 1);
+	assert_eq!(scores[0]["score"], 230);
+	assert_eq!(scores[0]["judgements"]["pgreat"], 100);
+	assert_eq!(scores[0]["scoreMeta"]["random"], "NONRAN");
+}
+
+#[test]
+fn skipped_scores_are_logged() {
+	let dir = player_dir("logged");
+	let missing = "0000000000000000000000000000000000000000000000000000000000000404";
+
+	Connection::open(dir.join("score.db"))
+		.unwrap()
+		.execute(
+			"INSERT INTO score VALUES (?1, 0, 6, 1600000300, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 0)",
+			[missing],
+		)
+		.unwrap();
+
+	let sink = Arc::new(MemorySink::default());
+	let ctx = ConvertContext::new(Logger::new(sink.clone()));
+
+	Beatoraja::convert(
+		&BeatorajaConfig {
+			score_path: dir.join("score.db"),
+			chart_path: dir.join("songdata.db"),
+			play_history: false,
+			scorelog_bests: false,
+			ln_modes: default_ln_modes(),
+			pms_playtype: None,
+		},
+		&ctx,
+	)
+	.unwrap();
+
+	let events = sink.events();
+	let skipped = events
+		.iter()
+		.find(|e| e.msg.contains(missing))
+		.expect("skip wasn't logged");
+
+	assert_eq!(skipped.level, SerializableLevel::Debug);
+	assert_eq!(
+		skipped.msg,
+		format!("Couldn't find a matching chart for score {missing}")
+	);
+}
+
+#[test]
+fn scorelog_only_when_asked_for() {
+	let dir = player_dir("no-scorelog");
+	let scores = convert(&dir, true);
+
+	assert_eq!(scores.len(), 1);
+	assert_eq!(scores[0]["score"], 230);
+}
+
+#[test]
+fn history_from_scorelog() {
+	let dir = player_dir("scorelog");
+	let converted = convert_with(&BeatorajaConfig {
+		score_path: dir.join("score.db"),
+		chart_path: dir.join("songdata.db"),
+		play_history: true,
+		scorelog_bests: true,
+		ln_modes: default_ln_modes(),
+		pms_playtype: None,
+	});
+	let scores = converted.output["k7"]["scores"].as_array().unwrap();
+
+	assert_eq!(scores.len(), 2);
+
+	assert_eq!(scores[0]["score"], 180);
+	assert_eq!(scores[0]["lamp"], "EASY CLEAR");
+	assert_eq!(scores[0]["timeAchieved"], 1600000100000i64);
+	assert_eq!(scores[0]["optional"]["bp"], 20);
+	assert_eq!(scores[0]["optional"]["maxCombo"], 80);
+	// scorelog has no judgements or random.
+	assert_eq!(scores[0]["judgements"], Value::Null);
+	assert_eq!(scores[0]["scoreMeta"]["random"], Value::Null);
+
+	assert_eq!(scores[1]["score"], 230);
+	assert_eq!(scores[1]["lamp"], "HARD CLEAR");
+}
+
+#[test]
+fn history_from_scoredatalog() {
+	let dir = player_dir("scoredatalog");
+
+	Connection::open(dir.join("scoredatalog.db"))
+		.unwrap()
+		.execute_batch(&format!(
+			"CREATE TABLE scoredatalog ({SCORE_COLUMNS});
+			INSERT INTO scoredatalog VALUES ('{SHA256}', 0, 1, 1600000000, 10, 10, 5, 5, 1, 1, 0, 0, 3, 3, 1, 1, 12, 40, 2);
+			INSERT INTO scoredatalog VALUES ('{SHA256}', 0, 4, 1600000100, 40, 40, 10, 10, 0, 0, 0, 0, 0, 0, 0, 0, 80, 20, 9);
+			INSERT INTO scoredatalog VALUES ('{SHA256}', 0, 6, 1600000200, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 0);"
+		))
+		.unwrap();
+
+	let scores = convert(&dir, true);
+
+	// The second play used an unfair random, so it's left out like it would be as a best.
+	assert_eq!(scores.len(), 2);
+
+	assert_eq!(
+		scores[0]["judgements"],
+		json!({ "pgreat": 20, "great": 10, "good": 2, "bad": 0, "poor": 8 })
+	);
+	assert_eq!(scores[0]["score"], 50);
+	assert_eq!(scores[0]["lamp"], "FAILED");
+	assert_eq!(scores[0]["scoreMeta"]["random"], "RANDOM");
+
+	assert_eq!(scores[1]["score"], 230);
+	assert_eq!(scores[1]["timeAchieved"], 1600000200000i64);
+}
+
+#[test]
+fn picked_ln_modes_only() {
+	let dir = player_dir("ln-modes");
+	let undefined_ln = "0000000000000000000000000000000000000000000000000000000000000002";
+
+	Connection::open(dir.join("songdata.db"))
+		.unwrap()
+		.execute(
+			"INSERT INTO song VALUES (?1, 'LN Song', '', 7, 1)",
+			[undefined_ln],
+		)
+		.unwrap();
+
+	let score = Connection::open(dir.join("score.db")).unwrap();
+	for (sha256, mode, date) in [
+		(undefined_ln, 0, 1600000300),
+		(undefined_ln, 1, 1600000400),
+		(undefined_ln, 2, 1600000500),
+		// Shouldn't happen, as beatoraja only uses mode 0 for charts with their own LN type.
+		(SHA256, 2, 1600000600),
+	] {
+		score
+			.execute(
+				"INSERT INTO score VALUES (?1, ?2, 5, ?3, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 0)",
+				(sha256, mode, date),
+			)
+			.unwrap();
+	}
+
+	let converted = convert_with(&BeatorajaConfig {
+		score_path: dir.join("score.db"),
+		chart_path: dir.join("songdata.db"),
+		play_history: false,
+		scorelog_bests: false,
+		ln_modes: vec![LNMode::LongNote, LNMode::HellChargeNote],
+		pms_playtype: None,
+	});
+	let scores = converted.output["k7"]["scores"].as_array().unwrap();
+
+	let comments: Vec<_> = scores
+		.iter()
+		.map(|e| (e["identifier"].as_str().unwrap(), e["comment"].clone()))
+		.collect();
+	assert_eq!(
+		comments,
+		[
+			(SHA256, Value::Null),
+			(undefined_ln, json!("LN mode")),
+			(undefined_ln, json!("HCN mode")),
+		]
+	);
+
+	let skips = &converted.report.skip_counts;
+	assert_eq!(skips.get(&SkipReason::LnModeNotPicked), Some(&1));
+	assert_eq!(skips.get(&SkipReason::LnModeUnused), Some(&1));
+}
+
+#[test]
+fn pms_charts_go_to_the_picked_playtype() {
+	let dir = player_dir("pms");
+	let pms = "0000000000000000000000000000000000000000000000000000000000000009";
+
+	Connection::open(dir.join("songdata.db"))
+		.unwrap()
+		.execute("INSERT INTO song VALUES (?1, 'Pop Song', '', 9, 0)", [pms])
+		.unwrap();
+	Connection::open(dir.join("score.db"))
+		.unwrap()
+		.execute(
+			"INSERT INTO score VALUES (?1, 0, 7, 1600000300, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 3)",
+			[pms],
+		)
+		.unwrap();
+
+	let config = |pms_playtype| BeatorajaConfig {
+		score_path: dir.join("score.db"),
+		chart_path: dir.join("songdata.db"),
+		play_history: false,
+		scorelog_bests: false,
+		ln_modes: default_ln_modes(),
+		pms_playtype,
+	};
+
+	let converted = convert_with(&config(None));
+	assert_eq!(converted.output["pms"], Value::Null);
+	assert_eq!(
+		converted.report.skip_counts.get(&SkipReason::NoPmsPlaytype),
+		Some(&1)
+	);
+
+	let converted = convert_with(&config(Some(PMSPlaytype::Keyboard)));
+	let bm = &converted.output["pms"];
+
+	assert_eq!(bm["meta"]["game"], "pms");
+	assert_eq!(bm["meta"]["playtype"], "Keyboard");
+	assert_eq!(
+		converted.output["k7"]["scores"].as_array().unwrap().len(),
+		1
+	);
+
+	let score = &bm["scores"][0];
+	assert_eq!(score["identifier"], pms);
+	assert_eq!(score["matchType"], "pmsChartHash");
+	assert_eq!(score["lamp"], "EX HARD CLEAR");
+	assert_eq!(score["score"], 230);
+	assert_eq!(
+		score["judgements"],
+		json!({ "cool": 100, "great": 30, "good": 0, "bad": 0, "poor": 0 })
+	);
+	assert_eq!(score["optional"]["ecool"], 50);
+	assert_eq!(score["optional"]["lgreat"], 15);
+	assert_eq!(score["optional"].get("epg"), None);
+	assert_eq!(
+		score["scoreMeta"],
+		json!({ "random": "R-RANDOM", "gauge": null })
+	);
+}
+
+#[test]
+fn cleared_dan_courses_become_classes() {
+	let dir = player_dir("courses");
+	let charts: Vec<_> = (1..=4).map(|i| format!("{i:064}")).collect::<Vec<_>>();
+
+	let songs = Connection::open(dir.join("songdata.db")).unwrap();
+	for sha256 in &charts[1..] {
+		songs
+			.execute(
+				"INSERT INTO song VALUES (?1, 'Dan Song', '', 7, 0)",
+				[sha256],
+			)
+			.unwrap();
+	}
+
+	let score = Connection::open(dir.join("score.db")).unwrap();
+	for (sha256, clear) in [
+		// Cleared, but beaten by the next one.
+		(charts[..2].concat(), 5),
+		(charts[2..].concat(), 6),
+		// Failed.
+		(charts[1..3].concat(), 1),
+		(charts[1..].concat(), 7),
+	] {
+		score
+			.execute(
+				"INSERT INTO score VALUES (?1, 0, ?2, 1600000300, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 0)",
+				(sha256, clear),
+			)
+			.unwrap();
+	}
+
+	let course = |name: &str, sha256: &[String], class: Option<&str>| Course {
+		name: name.into(),
+		sha256: sha256.to_vec(),
+		class: class.map(|class| DanClass {
+			set: "genocideDan".into(),
+			class: class.into(),
+		}),
+		..Default::default()
+	};
+
+	let converted = convert_with_courses(
+		&BeatorajaConfig {
+			score_path: dir.join("score.db"),
+			chart_path: dir.join("songdata.db"),
+			play_history: false,
+			scorelog_bests: false,
+			ln_modes: default_ln_modes(),
+			pms_playtype: None,
+		},
+		vec![
+			course("GENOCIDE 2018 発狂二段", &charts[..2], Some("INSANE_2")),
+			course("GENOCIDE 2018 発狂三段", &charts[2..], Some("INSANE_3")),
+			course("GENOCIDE 2018 発狂十段", &charts[1..3], Some("INSANE_10")),
+			// The name isn't enough to go on.
+			course("発狂皆伝 ★★ (^^)", &charts[1..], None),
+		],
+	);
+
+	let bm = &converted.output["k7"];
+	assert_eq!(bm["classes"], json!({ "genocideDan": "INSANE_3" }));
+	// Course scores aren't chart scores.
+	assert_eq!(bm["scores"].as_array().unwrap().len(), 1);
+	assert_eq!(
+		converted.report.skip_counts.get(&SkipReason::UnknownCourse),
+		None
+	);
+}
+
+#[test]
+#[ignore = "courses.json doesn't have the tables' course lists yet"]
+fn bundled_courses_are_for_known_classes() {
+	let courses = bundled_courses();
+	assert!(!courses.is_empty());
+
+	for course in &courses {
+		let class = course.class.as_ref().unwrap();
+
+		assert!(class.is_known(), "{}: {class:?}", course.name);
+		assert!(
+			!course.md5.is_empty() || !course.sha256.is_empty(),
+			"{} has no charts",
+			course.name
+		);
+	}
+
+	for class in known_classes() {
+		assert!(
+			courses.iter().any(|e| e.class.as_ref() == Some(&class)),
+			"No course for {class:?}"
+		);
+	}
+}
+
+#[test]
+#[ignore = "courses.json doesn't have the tables' course lists yet"]
+fn bundled_courses_become_classes() {
+	let course = bundled_courses()
+		.into_iter()
+		.find(|e| !e.sha256.is_empty())
+		.expect("no bundled course has sha256 hashes");
+	let class = course.class.clone().unwrap();
+
+	let dir = player_dir("bundled-courses");
+
+	let songs = Connection::open(dir.join("songdata.db")).unwrap();
+	for sha256 in &course.sha256 {
+		songs
+			.execute(
+				"INSERT INTO song VALUES (?1, 'Dan Song', '', 7, 0)",
+				[sha256],
+			)
+			.unwrap();
+	}
+
+	Connection::open(dir.join("score.db"))
+		.unwrap()
+		.execute(
+			"INSERT INTO score VALUES (?1, 0, 5, 1600000300, 50, 50, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 130, 0, 0)",
+			[course.sha256.concat()],
+		)
+		.unwrap();
+
+	let converted = convert_with_courses(
+		&BeatorajaConfig {
+			score_path: dir.join("score.db"),
+			chart_path: dir.join("songdata.db"),
+			play_history: false,
+			scorelog_bests: false,
+			ln_modes: default_ln_modes(),
+			pms_playtype: None,
+		},
+		bundled_courses(),
+	);
+
+	assert_eq!(
+		converted.output["k7"]["classes"],
+		json!({ class.set: class.class })
+	);
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use std::{env, fs, time::Duration};
+
+use import_scripts_lib::backend::{
+	checkpoint::Checkpoints,
+	client::{ClientOptions, TachiClient},
+	config::{ServerConfig, TISConfig},
+	jobs::{JobHandle, JobKind},
+	queue,
+};
+use serde_json::json;
+
+use common::{batch_manual, completed, import_accepted, in_temp_dir, MockServer};
+
+const IMPORT: &str = "/ir/direct-manual/import";
+const POLL: &str = "/api/v1/imports/1/poll-status";
+const TIME: i64 = 1600000000000;
+
+fn client(name: &str, server: &MockServer) -> TachiClient {
+	let config = ServerConfig {
+		name: name.into(),
+		base_url: server.base_url().into(),
+		..Default::default()
+	};
+
+	TachiClient::new(
+		&config,
+		"token",
+		ClientOptions {
+			initial_backoff: Duration::from_millis(10),
+			..Default::default()
+		},
+	)
+	.unwrap()
+}
+
+async fn import_to(name: &str) {
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	let item = queue::enqueue(Some("beatoraja"), name, &batch_manual()).unwrap();
+	queue::upload(
+		&client(name, &server),
+		item.id,
+		false,
+		&JobHandle::detached(JobKind::Import),
+	)
+	.await
+	.unwrap();
+
+	assert_eq!(server.requests_to(IMPORT).len(), 1);
+}
+
+fn checkpoint(server: &str) -> Option<i64> {
+	Checkpoints::load()
+		.for_source(server, "beatoraja")
+		.get("7K")
+		.copied()
+}
+
+// Checkpoints and the queue are files in the working directory, so it's all one test.
+#[tokio::test]
+async fn each_server_has_its_own_checkpoints() {
+	in_temp_dir();
+
+	// From before server profiles.
+	fs::write(
+		"tis-checkpoints.json",
+		json!({ "beatoraja": { "7K": 5 } }).to_string(),
+	)
+	.unwrap();
+	fs::write(
+		"tis-queue.json",
+		json!({
+			"nextId": 1,
+			"items": [{
+				"id": 1, "source": "beatoraja", "game": "bms", "playtype": "7K", "scores": 1,
+				"path": "batch-manual/queue/1.json", "added": 0, "state": "failed",
+				"attempts": 1, "error": null,
+			}],
+		})
+		.to_string(),
+	)
+	.unwrap();
+
+	let config_path = env::current_dir().unwrap().join("old-config.json");
+	fs::write(
+		&config_path,
+		json!({
+			"server": {
+				"name": "Self-hosted",
+				"base_url": "https://tachi.example.com",
+				"client_url": "https://tachi.example.com",
+				"client_id": "CI1",
+			},
+			"authToken": "token",
+			"warning": "",
+		})
+		.to_string(),
+	)
+	.unwrap();
+	let mut config = TISConfig::load_from(&config_path);
+
+	// Loading alone doesn't touch anything else.
+	assert_eq!(checkpoint("Self-hosted"), None);
+	assert_eq!(queue::list()[0].server, "");
+
+	config.finish_migration(&config_path);
+
+	// They belong to the profile the old config became.
+	assert_eq!(checkpoint("Self-hosted"), Some(5));
+	assert_eq!(queue::list()[0].server, "Self-hosted");
+
+	// And the config was saved as that profile.
+	let saved: serde_json::Value =
+		serde_json::from_slice(&fs::read(&config_path).unwrap()).unwrap();
+	assert_eq!(saved["servers"][0]["name"], "Self-hosted");
+	assert_eq!(saved["servers"][0]["auth_token"], "token");
+	assert!(saved.get("authToken").is_none());
+
+	import_to("Bokutachi").await;
+
+	assert_eq!(checkpoint("Bokutachi"), Some(TIME));
+	// Importing to one server doesn't mean another has the scores.
+	assert_eq!(checkpoint("Staging"), None);
+	assert_eq!(checkpoint("Self-hosted"), Some(5));
+
+	import_to("Staging").await;
+
+	assert_eq!(checkpoint("Staging"), Some(TIME));
+	assert_eq!(checkpoint("Bokutachi"), Some(TIME));
+}
@@ -0,0 +1,289 @@
+//! A scriptable stand-in for a Tachi server, for testing the import flow offline.
+
+#![allow(dead_code)]
+
+use std::{
+	collections::HashMap,
+	env, fs,
+	io::{BufRead, BufReader, Read, Write},
+	net::{TcpListener, TcpStream},
+	sync::{Arc, Mutex, Once},
+	thread,
+};
+
+use serde_json::json;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+	pub method: String,
+	pub path: String,
+	pub headers: HashMap<String, String>,
+	pub body: String,
+}
+
+impl Request {
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.headers.get(&name.to_lowercase()).map(String::as_str)
+	}
+
+	pub fn json(&self) -> serde_json::Value {
+		serde_json::from_str(&self.body).expect("request body wasn't JSON")
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+	pub status: u16,
+	pub headers: Vec<(String, String)>,
+	pub body: String,
+}
+
+impl Response {
+	pub fn json(status: u16, body: serde_json::Value) -> Self {
+		Self {
+			status,
+			headers: vec![],
+			body: body.to_string(),
+		}
+	}
+
+	pub fn raw(status: u16, body: &str) -> Self {
+		Self {
+			status,
+			headers: vec![],
+			body: body.into(),
+		}
+	}
+
+	pub fn with_header(mut self, name: &str, value: &str) -> Self {
+		self.headers.push((name.into(), value.into()));
+		self
+	}
+}
+
+type Routes = HashMap<(String, String), Vec<Response>>;
+
+/// Each route has a script of responses, handed out in order. The last one repeats.
+/// Unscripted routes get a 404.
+pub struct MockServer {
+	base_url: String,
+	routes: Arc<Mutex<Routes>>,
+	requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+	pub fn start() -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").expect("couldn't bind mock server");
+		let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+		let routes: Arc<Mutex<Routes>> = Default::default();
+		let requests: Arc<Mutex<Vec<Request>>> = Default::default();
+
+		{
+			let routes = routes.clone();
+			let requests = requests.clone();
+
+			thread::spawn(move || {
+				for stream in listener.incoming().flatten() {
+					let routes = routes.clone();
+					let requests = requests.clone();
+
+					thread::spawn(move || handle(stream, &routes, &requests));
+				}
+			});
+		}
+
+		Self {
+			base_url,
+			routes,
+			requests,
+		}
+	}
+
+	pub fn url(&self, path: &str) -> String {
+		format!("{}{path}", self.base_url)
+	}
+
+	pub fn base_url(&self) -> &str {
+		&self.base_url
+	}
+
+	pub fn on(&self, method: &str, path: &str, responses: Vec<Response>) -> &Self {
+		self.routes
+			.lock()
+			.unwrap()
+			.insert((method.into(), path.into()), responses);
+		self
+	}
+
+	pub fn requests(&self) -> Vec<Request> {
+		self.requests.lock().unwrap().clone()
+	}
+
+	pub fn requests_to(&self, path: &str) -> Vec<Request> {
+		self.requests()
+			.into_iter()
+			.filter(|e| e.path == path)
+			.collect()
+	}
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+	let mut reader = BufReader::new(stream);
+
+	let mut line = String::new();
+	reader.read_line(&mut line).ok()?;
+	let mut parts = line.split_whitespace();
+	let method = parts.next()?.to_owned();
+	let path = parts.next()?.to_owned();
+
+	let mut headers = HashMap::new();
+	loop {
+		let mut line = String::new();
+		reader.read_line(&mut line).ok()?;
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		let (name, value) = line.split_once(':')?;
+		headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+	}
+
+	let len = headers
+		.get("content-length")
+		.and_then(|e| e.parse().ok())
+		.unwrap_or(0);
+	let mut body = vec![0; len];
+	reader.read_exact(&mut body).ok()?;
+
+	Some(Request {
+		method,
+		path,
+		headers,
+		body: String::from_utf8_lossy(&body).into_owned(),
+	})
+}
+
+fn handle(mut stream: TcpStream, routes: &Mutex<Routes>, requests: &Mutex<Vec<Request>>) {
+	let Some(req) = read_request(&stream) else {
+		return;
+	};
+
+	let res = {
+		let mut routes = routes.lock().unwrap();
+
+		match routes.get_mut(&(req.method.clone(), req.path.clone())) {
+			Some(script) if script.len() > 1 => script.remove(0),
+			Some(script) if !script.is_empty() => script[0].clone(),
+			_ => Response::raw(404, "not found"),
+		}
+	};
+
+	requests.lock().unwrap().push(req);
+
+	let mut out = format!(
+		"HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n",
+		res.status,
+		res.body.len()
+	);
+	for (name, value) in &res.headers {
+		out.push_str(&format!("{name}: {value}\r\n"));
+	}
+	out.push_str("\r\n");
+	out.push_str(&res.body);
+
+	let _ = stream.write_all(out.as_bytes());
+}
+
+/// Imports write a copy of every batch manual to `batch-manual/`, so keep that out of the repo.
+pub fn in_temp_dir() {
+	static ONCE: Once = Once::new();
+
+	ONCE.call_once(|| {
+		let dir = env::temp_dir().join(format!("tis-tests-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		env::set_current_dir(&dir).unwrap();
+	});
+}
+
+pub fn batch_manual() -> serde_json::Value {
+	json!({
+		"meta": { "game": "bms", "playtype": "7K", "service": "TIS. v2.2.0" },
+		"scores": [{
+			"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+			"matchType": "bmsChartHash",
+			"score": 1000,
+			"lamp": "CLEAR",
+			"timeAchieved": 1600000000000i64,
+		}],
+		"classes": {},
+	})
+}
+
+pub fn import_accepted(server: &MockServer) -> Response {
+	Response::json(
+		200,
+		json!({
+			"success": true,
+			"description": "Import loaded into queue.",
+			"body": { "url": server.url("/api/v1/imports/1/poll-status") },
+		}),
+	)
+}
+
+pub fn ongoing(description: &str) -> Response {
+	Response::json(
+		200,
+		json!({
+			"success": true,
+			"description": "Import is ongoing.",
+			"body": {
+				"importStatus": "ongoing",
+				"progress": { "description": description },
+			},
+		}),
+	)
+}
+
+pub fn completed(score_ids: &[&str], errors: usize) -> Response {
+	Response::json(
+		200,
+		json!({
+			"success": true,
+			"description": "Import completed.",
+			"body": {
+				"importStatus": "completed",
+				"import": {
+					"importID": "1",
+					"game": "bms",
+					"playtypes": ["7K"],
+					"scoreIDs": score_ids,
+					"errors": (0..errors).map(|i| json!({ "type": "InvalidDatapoint", "message": format!("error {i}") })).collect::<Vec<_>>(),
+					"createdSessions": [{ "sessionID": "Q1", "type": "Created" }],
+					"classDeltas": [],
+				},
+			},
+		}),
+	)
+}
+
+pub const STATUS: &str = "/api/v1/status";
+pub const ME: &str = "/api/v1/users/me";
+
+pub fn status(whoami: Option<u64>, permissions: &[&str]) -> Response {
+	Response::json(
+		200,
+		json!({
+			"success": true,
+			"description": "Status check successful.",
+			"body": { "serverTime": 0, "version": "3.0.0", "whoami": whoami, "permissions": permissions },
+		}),
+	)
+}
+
+pub fn me() -> Response {
+	Response::json(
+		200,
+		json!({ "success": true, "description": "Found user.", "body": { "id": 1, "username": "zkldi" } }),
+	)
+}
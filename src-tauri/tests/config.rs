@@ -0,0 +1,78 @@
+use std::{env, fs};
+
+use import_scripts_lib::backend::config::{ServerConfig, TISConfig};
+use serde_json::json;
+
+fn load(name: &str, config: serde_json::Value) -> TISConfig {
+	let path = env::temp_dir().join(format!("tis-config-{}-{name}.json", std::process::id()));
+	fs::write(&path, config.to_string()).unwrap();
+
+	let config = TISConfig::load_from(&path);
+	fs::remove_file(&path).unwrap();
+	config
+}
+
+#[test]
+fn old_configs_become_one_profile() {
+	let config = load(
+		"old",
+		json!({
+			"server": {
+				"name": "Self-hosted",
+				"base_url": "https://tachi.example.com",
+				"client_url": "https://tachi.example.com",
+				"client_id": "CI1",
+			},
+			"authToken": "token",
+			"warning": "",
+			"staging": true,
+		}),
+	);
+
+	assert_eq!(config.servers.len(), 1);
+
+	let server = config.active_server().unwrap();
+	assert_eq!(server.name, "Self-hosted");
+	assert_eq!(server.auth_token.as_deref(), Some("token"));
+
+	// The old keys aren't written back out.
+	let saved = serde_json::to_value(&config).unwrap();
+	assert!(saved.get("server").is_none() && saved.get("authToken").is_none());
+}
+
+#[test]
+fn picks_servers_by_name() {
+	let mut config = load(
+		"profiles",
+		json!({
+			"servers": [
+				{ "name": "Bokutachi", "base_url": "https://boku.tachi.ac", "client_url": "", "client_id": "" },
+				{ "name": "Staging", "base_url": "https://staging.tachi.ac", "client_url": "", "client_id": "", "auth_token": "s" },
+			],
+			"activeServer": "Staging",
+			"warning": "",
+		}),
+	);
+
+	assert_eq!(config.active_server().unwrap().name, "Staging");
+	assert_eq!(
+		config.pick_server(Some("Bokutachi")).unwrap().base_url,
+		"https://boku.tachi.ac"
+	);
+	assert!(config.pick_server(Some("Nowhere")).is_err());
+
+	config.upsert_server(ServerConfig {
+		name: "Staging".into(),
+		base_url: "https://staging2.tachi.ac".into(),
+		..Default::default()
+	});
+	assert_eq!(config.servers.len(), 2);
+	assert_eq!(
+		config.active_server().unwrap().base_url,
+		"https://staging2.tachi.ac"
+	);
+
+	// Removing the active server falls back to the first one.
+	config.servers.retain(|e| e.name != "Staging");
+	assert_eq!(config.active_server().unwrap().name, "Bokutachi");
+}
@@ -0,0 +1,704 @@
+mod common;
+
+use std::{
+	fs,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use import_scripts_lib::backend::{
+	client::{ClientOptions, TachiClient},
+	config::ServerConfig,
+	history::{self, HistoryQuery},
+	import::{import_batch_manual, revert_import},
+	jobs::{Cancelled, JobEvent, JobHandle, JobKind, JobSink, Jobs},
+	upload::{import_in_chunks, split_batch_manual},
+};
+use serde_json::json;
+
+use common::{
+	batch_manual, completed, import_accepted, in_temp_dir, ongoing, MockServer, Response,
+};
+
+const IMPORT: &str = "/ir/direct-manual/import";
+const POLL: &str = "/api/v1/imports/1/poll-status";
+
+fn client_with(server: &MockServer, options: ClientOptions) -> TachiClient {
+	let config = ServerConfig {
+		base_url: server.base_url().into(),
+		client_url: "https://tachi.example.com".into(),
+		..Default::default()
+	};
+
+	TachiClient::new(&config, "token", options).unwrap()
+}
+
+fn job() -> JobHandle {
+	JobHandle::detached(JobKind::Import)
+}
+
+fn client(server: &MockServer) -> TachiClient {
+	client_with(
+		server,
+		ClientOptions {
+			initial_backoff: Duration::from_millis(10),
+			..Default::default()
+		},
+	)
+}
+
+#[tokio::test]
+async fn successful_import() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a", "b"], 1)]);
+
+	let doc = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(doc.import_id, "1");
+	assert_eq!(doc.game, "bms");
+	assert_eq!(doc.playtypes, ["7K"]);
+	assert_eq!(doc.score_ids, ["a", "b"]);
+	assert_eq!(doc.errors.len(), 1);
+	assert_eq!(doc.errors[0].kind, "InvalidDatapoint");
+	assert_eq!(doc.created_sessions[0].session_id, "Q1");
+
+	let submitted = &server.requests_to(IMPORT)[0];
+	assert_eq!(submitted.header("authorization"), Some("Bearer token"));
+	assert_eq!(submitted.header("x-user-intent"), Some("true"));
+	assert_eq!(submitted.json(), batch_manual());
+}
+
+#[tokio::test]
+async fn writes_a_summary_next_to_the_batch_manual() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on(
+			"GET",
+			POLL,
+			vec![Response::json(
+				200,
+				json!({
+					"success": true,
+					"description": "Import completed.",
+					"body": {
+						"importStatus": "completed",
+						"import": {
+							"importID": "summary-test",
+							"game": "bms",
+							"playtypes": ["7K"],
+							"scoreIDs": ["a"],
+							"errors": [{ "type": "SongOrChartNotFound", "message": "No chart abc." }],
+							"createdSessions": [],
+							"classDeltas": [{ "set": "dan", "playtype": "7K", "old": null, "new": "DAN_1" }],
+						},
+					},
+				}),
+			)],
+		);
+
+	let doc = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(doc.class_deltas[0].new, "DAN_1");
+
+	let summary = fs::read_dir("batch-manual")
+		.unwrap()
+		.flatten()
+		.map(|e| fs::read_to_string(e.path()).unwrap())
+		.find(|e| e.contains("summary-test"))
+		.expect("no summary written");
+
+	assert!(summary.contains("dan (7K): none -> DAN_1"), "{summary}");
+	assert!(
+		summary.contains("SongOrChartNotFound: No chart abc."),
+		"{summary}"
+	);
+}
+
+#[tokio::test]
+async fn finished_imports_are_recorded_in_history() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a"], 1)]);
+
+	import_batch_manual(
+		&client(&server),
+		&batch_manual(),
+		Some("history-test"),
+		true,
+		&job(),
+	)
+	.await
+	.unwrap();
+
+	let records = history::query(&HistoryQuery {
+		source: Some("history-test".into()),
+		..Default::default()
+	});
+
+	assert_eq!(records.len(), 1);
+	assert_eq!(records[0].import_id, "1");
+	assert_eq!(records[0].url, "https://tachi.example.com/imports/1");
+	assert_eq!(
+		(records[0].game.as_str(), records[0].playtype.as_str()),
+		("bms", "7K")
+	);
+	assert_eq!(
+		(
+			records[0].scores_sent,
+			records[0].new_scores,
+			records[0].failed
+		),
+		(1, 1, 1)
+	);
+	assert!(fs::exists(&records[0].batch_manual).unwrap());
+}
+
+#[tokio::test]
+async fn reverting_marks_the_import_in_history() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a"], 0)])
+		.on(
+			"POST",
+			"/api/v1/imports/1/revert",
+			vec![Response::json(
+				200,
+				json!({ "success": true, "description": "Reverted import.", "body": {} }),
+			)],
+		);
+
+	let client = client(&server);
+	import_batch_manual(&client, &batch_manual(), Some("revert-test"), true, &job())
+		.await
+		.unwrap();
+	revert_import(&client, "1").await.unwrap();
+
+	let revert = &server.requests_to("/api/v1/imports/1/revert")[0];
+	assert_eq!(revert.method, "POST");
+	assert_eq!(revert.header("authorization"), Some("Bearer token"));
+
+	let records = history::query(&HistoryQuery {
+		source: Some("revert-test".into()),
+		..Default::default()
+	});
+	assert!(records[0].reverted);
+}
+
+#[tokio::test]
+async fn failed_revert_reports_description() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server.on(
+		"POST",
+		"/api/v1/imports/2/revert",
+		vec![Response::json(
+			403,
+			json!({ "success": false, "description": "You are not authorised to revert this import." }),
+		)],
+	);
+
+	let err = revert_import(&client(&server), "2").await.unwrap_err();
+
+	assert!(
+		err.to_string().contains("not authorised to revert"),
+		"{err}"
+	);
+}
+
+#[tokio::test]
+async fn automated_imports_are_not_user_intent() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&[], 0)]);
+
+	import_batch_manual(&client(&server), &batch_manual(), None, false, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(
+		server.requests_to(IMPORT)[0].header("x-user-intent"),
+		Some("false")
+	);
+}
+
+#[tokio::test]
+async fn polls_until_completed() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on(
+			"GET",
+			POLL,
+			vec![
+				ongoing("Importing scores."),
+				ongoing("Processing sessions."),
+				completed(&["a"], 0),
+			],
+		);
+
+	let doc = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(doc.score_ids.len(), 1);
+	assert_eq!(server.requests_to(POLL).len(), 3);
+}
+
+#[tokio::test]
+async fn rejected_import_reports_description() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server.on(
+		"POST",
+		IMPORT,
+		vec![Response::json(
+			400,
+			json!({ "success": false, "description": "Invalid batch-manual: scores[0].lamp." }),
+		)],
+	);
+
+	let err = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(
+		err.to_string()
+			.contains("Invalid batch-manual: scores[0].lamp."),
+		"{err}"
+	);
+	assert!(server.requests_to(POLL).is_empty());
+}
+
+#[tokio::test]
+async fn invalid_scores_are_left_out() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	let mut bm = batch_manual();
+	let mut bad = bm["scores"][0].clone();
+	bad["identifier"] = json!("abc");
+	bm["scores"].as_array_mut().unwrap().insert(0, bad);
+
+	// Looks wrong, but Tachi takes it.
+	let mut odd = bm["scores"][1].clone();
+	odd["lamp"] = json!("FULL COMBO");
+	odd["optional"] = json!({ "bp": 2 });
+	bm["scores"].as_array_mut().unwrap().push(odd.clone());
+
+	let docs = import_in_chunks(&client(&server), &bm, None, true, &job())
+		.await
+		.unwrap();
+
+	let mut expected = batch_manual();
+	expected["scores"].as_array_mut().unwrap().push(odd);
+	assert_eq!(server.requests_to(IMPORT)[0].json(), expected);
+
+	assert_eq!(docs[0].left_out.len(), 1);
+	assert_eq!(docs[0].left_out[0].index, Some(0));
+	assert!(docs[0].to_string().contains("Left out (1):"));
+}
+
+#[tokio::test]
+async fn invalid_batch_manuals_are_not_sent() {
+	in_temp_dir();
+	let server = MockServer::start();
+
+	let mut bm = batch_manual();
+	bm["meta"]["playtype"] = json!("5K");
+
+	let err = import_in_chunks(&client(&server), &bm, None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(
+		err.to_string()
+			.contains("has 1 problem:\nUnsupported game/playtype bms 5K."),
+		"{err}"
+	);
+	assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn failed_processing_reports_description() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on(
+			"GET",
+			POLL,
+			vec![Response::json(
+				200,
+				json!({ "success": false, "description": "Import failed: user is banned." }),
+			)],
+		);
+
+	let err = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(
+		err.to_string().contains("Import failed: user is banned."),
+		"{err}"
+	);
+}
+
+#[tokio::test]
+async fn malformed_json_is_an_error() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server.on("POST", IMPORT, vec![Response::raw(200, "{\"success\": tr")]);
+
+	let err = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(err.to_string().contains("Invalid response"), "{err}");
+}
+
+#[tokio::test]
+async fn malformed_poll_json_is_an_error() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on(
+			"GET",
+			POLL,
+			vec![Response::json(
+				200,
+				json!({ "success": true, "description": "?", "body": { "importStatus": "exploded" } }),
+			)],
+		);
+
+	let err = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(err.to_string().contains("Invalid response"), "{err}");
+}
+
+#[tokio::test]
+async fn submit_server_error_is_not_retried() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server.on(
+		"POST",
+		IMPORT,
+		vec![Response::raw(502, "<html>Bad Gateway</html>")],
+	);
+
+	let err = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(err.to_string().contains("502"), "{err}");
+	assert!(server.requests_to(POLL).is_empty());
+}
+
+#[tokio::test]
+async fn poll_server_errors_are_retried() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on(
+			"GET",
+			POLL,
+			vec![
+				Response::raw(503, "Service Unavailable"),
+				Response::raw(500, "Internal Server Error"),
+				completed(&["a"], 0),
+			],
+		);
+
+	let doc = import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(doc.score_ids.len(), 1);
+	assert_eq!(server.requests_to(POLL).len(), 3);
+}
+
+#[tokio::test]
+async fn poll_gives_up_after_max_retries() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![Response::raw(503, "Service Unavailable")]);
+
+	let client = client_with(
+		&server,
+		ClientOptions {
+			initial_backoff: Duration::from_millis(10),
+			max_retries: 2,
+			..Default::default()
+		},
+	);
+
+	let err = import_batch_manual(&client, &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(err.to_string().contains("failed 3 times"), "{err}");
+	assert_eq!(server.requests_to(POLL).len(), 3);
+}
+
+#[tokio::test]
+async fn rate_limited_submit_honours_retry_after() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on(
+			"POST",
+			IMPORT,
+			vec![
+				Response::raw(429, "slow down").with_header("Retry-After", "1"),
+				import_accepted(&server),
+			],
+		)
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	let start = Instant::now();
+	import_batch_manual(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(server.requests_to(IMPORT).len(), 2);
+	assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn gives_up_at_the_deadline() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![ongoing("Importing scores.")]);
+
+	let client = client_with(
+		&server,
+		ClientOptions {
+			deadline: Duration::from_secs(2),
+			..Default::default()
+		},
+	);
+
+	let err = import_batch_manual(&client, &batch_manual(), None, true, &job())
+		.await
+		.unwrap_err();
+
+	assert!(err.to_string().contains("Gave up"), "{err}");
+}
+
+#[derive(Default)]
+struct Events(Mutex<Vec<JobEvent>>);
+
+impl JobSink for Events {
+	fn emit(&self, event: &JobEvent) {
+		self.0.lock().unwrap().push(event.clone());
+	}
+}
+
+#[tokio::test]
+async fn cancelling_stops_polling() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![ongoing("Importing scores.")]);
+
+	let events = Arc::new(Events::default());
+	let jobs = Arc::new(Jobs::new(events.clone()));
+	let job = jobs.start(JobKind::Import);
+
+	{
+		let jobs = jobs.clone();
+		let id = job.id();
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_millis(1500)).await;
+			assert!(jobs.cancel(id));
+		});
+	}
+
+	let res = import_batch_manual(&client(&server), &batch_manual(), None, true, &job).await;
+	jobs.finish(&job, &res);
+
+	assert!(res.unwrap_err().is::<Cancelled>());
+	assert!(!jobs.cancel(job.id()));
+
+	let statuses: Vec<_> = events
+		.0
+		.lock()
+		.unwrap()
+		.iter()
+		.map(|e| serde_json::to_value(e).unwrap()["status"].clone())
+		.collect();
+	assert_eq!(statuses, ["started", "importing", "cancelled"]);
+}
+
+#[tokio::test]
+async fn cancelling_stops_backing_off() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server.on(
+		"POST",
+		IMPORT,
+		vec![Response::raw(429, "slow down").with_header("Retry-After", "30")],
+	);
+
+	let jobs = Arc::new(Jobs::new(()));
+	let job = jobs.start(JobKind::Import);
+
+	{
+		let jobs = jobs.clone();
+		let id = job.id();
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_millis(300)).await;
+			assert!(jobs.cancel(id));
+		});
+	}
+
+	let start = Instant::now();
+	let res = import_batch_manual(&client(&server), &batch_manual(), None, true, &job).await;
+
+	assert!(res.unwrap_err().is::<Cancelled>());
+	assert!(start.elapsed() < Duration::from_secs(5));
+	assert_eq!(server.requests_to(IMPORT).len(), 1);
+}
+
+fn big_batch_manual() -> serde_json::Value {
+	let mut bm = batch_manual();
+	bm["scores"] = (0..10)
+		.rev()
+		.map(|i| {
+			json!({
+				"identifier": format!("{i:032x}"),
+				"matchType": "bmsChartHash",
+				"score": 1000,
+				"lamp": "CLEAR",
+				"timeAchieved": 1600000000000i64 + i,
+			})
+		})
+		.collect();
+	bm["classes"] = json!({ "dan": "DAN_1" });
+	bm
+}
+
+fn chunking_client(server: &MockServer) -> TachiClient {
+	client_with(
+		server,
+		ClientOptions {
+			initial_backoff: Duration::from_millis(10),
+			max_chunk_bytes: 600,
+			..Default::default()
+		},
+	)
+}
+
+#[test]
+fn splits_oldest_scores_first_with_classes_last() {
+	let bm = big_batch_manual();
+	let chunks = split_batch_manual(&bm, 600);
+
+	assert!(chunks.len() > 1);
+	assert!(chunks.iter().all(|e| e.to_string().len() <= 600));
+	assert!(chunks.iter().all(|e| e["meta"] == bm["meta"]));
+
+	let times: Vec<_> = chunks
+		.iter()
+		.flat_map(|e| e["scores"].as_array().unwrap().clone())
+		.map(|e| e["timeAchieved"].as_i64().unwrap())
+		.collect();
+	assert_eq!(
+		times,
+		(0..10).map(|i| 1600000000000i64 + i).collect::<Vec<_>>()
+	);
+
+	let (last, rest) = chunks.split_last().unwrap();
+	assert_eq!(last["classes"], bm["classes"]);
+	assert!(rest.iter().all(|e| e.get("classes").is_none()));
+}
+
+#[tokio::test]
+async fn small_batch_manuals_are_one_import() {
+	in_temp_dir();
+	let server = MockServer::start();
+	server
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	let documents = import_in_chunks(&client(&server), &batch_manual(), None, true, &job())
+		.await
+		.unwrap();
+
+	assert_eq!(documents.len(), 1);
+	assert_eq!(server.requests_to(IMPORT)[0].json(), batch_manual());
+}
+
+#[tokio::test]
+async fn chunked_upload_resumes_from_the_failed_chunk() {
+	in_temp_dir();
+	let bm = big_batch_manual();
+	let chunks = split_batch_manual(&bm, 600);
+
+	let server = MockServer::start();
+	server
+		.on(
+			"POST",
+			IMPORT,
+			vec![
+				import_accepted(&server),
+				Response::json(
+					400,
+					json!({ "success": false, "description": "Try again later." }),
+				),
+				import_accepted(&server),
+			],
+		)
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	let err = import_in_chunks(&chunking_client(&server), &bm, None, true, &job())
+		.await
+		.unwrap_err();
+	assert!(
+		err.to_string()
+			.contains(&format!("Chunk 2 of {} failed", chunks.len())),
+		"{err}"
+	);
+
+	let documents = import_in_chunks(&chunking_client(&server), &bm, None, true, &job())
+		.await
+		.unwrap();
+	assert_eq!(documents.len(), chunks.len() - 1);
+
+	let sent: Vec<_> = server
+		.requests_to(IMPORT)
+		.iter()
+		.map(|e| e.json())
+		.collect();
+	// The first chunk isn't sent again, the failed one is.
+	assert_eq!(sent[0], chunks[0]);
+	assert_eq!(sent[1], chunks[1]);
+	assert_eq!(sent[2..], chunks[1..]);
+}
@@ -0,0 +1,199 @@
+use std::{
+	env, fs,
+	path::{Path, PathBuf},
+};
+
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use import_scripts_lib::backend::{
+	config::LR2Config,
+	courses::{load_lr2crs, parse_lr2crs, Course, DanClass},
+	log::{Logger, SerializableLevel, StderrSink},
+	lr2::LR2,
+	report::{Converted, SkipReason},
+	source::{ConvertContext, ScoreSource},
+};
+
+/// Three 7K charts, and a best on the first.
+fn lr2_dir(name: &str) -> (PathBuf, Vec<String>) {
+	let dir = env::temp_dir().join(format!("tis-lr2-{name}-{}", std::process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+
+	let charts: Vec<String> = (1..=3).map(|i| format!("{i:032}")).collect();
+
+	let songs = Connection::open(dir.join("song.db")).unwrap();
+	songs
+		.execute_batch("CREATE TABLE song (hash TEXT, title TEXT, subtitle TEXT, mode INTEGER);")
+		.unwrap();
+	for md5 in &charts {
+		songs
+			.execute("INSERT INTO song VALUES (?1, 'Song', '', 7)", [md5])
+			.unwrap();
+	}
+
+	Connection::open(dir.join("score.db"))
+		.unwrap()
+		.execute_batch(&format!(
+			"CREATE TABLE score (
+				hash TEXT, clear INTEGER, perfect INTEGER, great INTEGER, good INTEGER,
+				bad INTEGER, poor INTEGER, maxcombo INTEGER, minbp INTEGER, op_best INTEGER,
+				complete INTEGER
+			);
+			INSERT INTO score VALUES ('{}', 4, 100, 30, 0, 0, 0, 130, 0, 0, 1);",
+			charts[0]
+		))
+		.unwrap();
+
+	(dir, charts)
+}
+
+fn add_score(dir: &Path, hash: &str, clear: i32) {
+	Connection::open(dir.join("score.db"))
+		.unwrap()
+		.execute(
+			"INSERT INTO score VALUES (?1, ?2, 100, 30, 0, 0, 0, 130, 0, 0, 1)",
+			(hash, clear),
+		)
+		.unwrap();
+}
+
+fn convert(dir: &Path, courses: Vec<Course>, course_path: Option<PathBuf>) -> Converted<Value> {
+	let mut ctx = ConvertContext::new(Logger::new(StderrSink {
+		min_level: SerializableLevel::Error,
+	}));
+	ctx.courses = courses;
+
+	let converted = LR2::convert(
+		&LR2Config {
+			score_path: dir.join("score.db"),
+			chart_path: dir.join("song.db"),
+			pms_playtype: None,
+			course_path,
+		},
+		&ctx,
+	)
+	.unwrap();
+
+	Converted {
+		output: serde_json::to_value(converted.output).unwrap(),
+		report: converted.report,
+	}
+}
+
+fn lr2crs(courses: &[(&str, String)]) -> Vec<u8> {
+	let mut xml = String::from("<?xml version=\"1.0\" encoding=\"shift_jis\"?>\n<courselist>\n");
+	for (title, hash) in courses {
+		xml.push_str(&format!(
+			"<course>\n<title>{title}</title>\n<line>3</line>\n<hash>{hash}</hash>\n<type>2</type>\n</course>\n"
+		));
+	}
+	xml.push_str("</courselist>\n");
+
+	encoding_rs::SHIFT_JIS.encode(&xml).0.into_owned()
+}
+
+#[test]
+fn cleared_courses_from_lr2crs_become_classes() {
+	let (dir, charts) = lr2_dir("courses");
+	let prefix = "0".repeat(32);
+
+	let course_dir = dir.join("LR2files").join("Course");
+	fs::create_dir_all(&course_dir).unwrap();
+	fs::write(
+		course_dir.join("genocide.lr2crs"),
+		lr2crs(&[
+			("GENOCIDE 七段", format!("{prefix}{}", charts.concat())),
+			("GENOCIDE 八段", format!("{prefix}{}", charts[1..].concat())),
+			("GENOCIDE 九段", format!("{prefix}{}", charts[..2].concat())),
+		]),
+	)
+	.unwrap();
+
+	add_score(&dir, &format!("{prefix}{}", charts.concat()), 3);
+	// Easy gauge doesn't count.
+	add_score(&dir, &format!("{prefix}{}", charts[1..].concat()), 2);
+	add_score(&dir, &format!("{prefix}{}", charts[..2].concat()), 1);
+	add_score(&dir, &format!("{prefix}{}", charts[2]), 3);
+	add_score(&dir, &format!("{prefix}{}{}", charts[2], charts[0]), 4);
+
+	// .lr2crs files don't say what a course is worth, so that has to come from elsewhere.
+	let known = vec![Course {
+		name: "GENOCIDE 七段".into(),
+		md5: charts.clone(),
+		class: Some(DanClass {
+			set: "genocideDan".into(),
+			class: "NORMAL_7".into(),
+		}),
+		..Default::default()
+	}];
+
+	let converted = convert(&dir, known.clone(), Some(dir.join("LR2files")));
+
+	let bm = &converted.output["k7"];
+	assert_eq!(bm["classes"], json!({ "genocideDan": "NORMAL_7" }));
+	assert_eq!(bm["scores"].as_array().unwrap().len(), 1);
+
+	let skips = &converted.report.skip_counts;
+	assert_eq!(skips.get(&SkipReason::UnknownCourse), Some(&1));
+	// A single chart behind the prefix isn't a course.
+	assert_eq!(skips.get(&SkipReason::ChartNotFound), Some(&1));
+
+	// Without the course files, only the dan is known.
+	let converted = convert(&dir, known, None);
+	assert_eq!(
+		converted.output["k7"]["classes"],
+		json!({ "genocideDan": "NORMAL_7" })
+	);
+	assert_eq!(
+		converted.report.skip_counts.get(&SkipReason::UnknownCourse),
+		Some(&3)
+	);
+}
+
+#[test]
+fn lr2crs_titles_are_decoded() {
+	let hash = format!("{}{}", "0".repeat(32), "a".repeat(64));
+	let courses = parse_lr2crs(&lr2crs(&[
+		("発狂皆伝 &amp; more", hash.clone()),
+		("Missing hash", String::new()),
+	]));
+
+	assert_eq!(courses.len(), 1);
+	assert_eq!(courses[0].name, "発狂皆伝 & more");
+	assert_eq!(courses[0].md5, ["a".repeat(32), "a".repeat(32)]);
+
+	// Not guessed from the name.
+	assert_eq!(courses[0].class, None);
+
+	let utf8 = String::from_utf8(lr2crs(&[("Stella st3", hash)]))
+		.unwrap_or_default()
+		.replace("shift_jis", "UTF-8");
+	assert_eq!(parse_lr2crs(utf8.as_bytes())[0].name, "Stella st3");
+}
+
+#[cfg(unix)]
+#[test]
+fn course_folders_dont_loop_through_symlinks() {
+	let (dir, charts) = lr2_dir("course-symlinks");
+
+	let course_dir = dir.join("Course");
+	fs::create_dir_all(&course_dir).unwrap();
+	fs::write(
+		course_dir.join("genocide.lr2crs"),
+		lr2crs(&[("GENOCIDE 七段", charts.concat())]),
+	)
+	.unwrap();
+	std::os::unix::fs::symlink(&dir, course_dir.join("loop")).unwrap();
+
+	let courses = load_lr2crs(
+		&course_dir,
+		&Logger::new(StderrSink {
+			min_level: SerializableLevel::Error,
+		}),
+	);
+
+	assert_eq!(courses.len(), 1);
+	assert_eq!(courses[0].name, "GENOCIDE 七段");
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use std::{net::TcpListener, time::Duration};
+
+use import_scripts_lib::backend::{config::ServerConfig, oauth};
+use reqwest::Url;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use common::{MockServer, Response};
+
+const TOKEN: &str = "/api/v1/oauth/token";
+
+fn server(mock: &MockServer) -> ServerConfig {
+	ServerConfig {
+		name: "Stand-in".into(),
+		base_url: mock.base_url().into(),
+		client_url: mock.base_url().into(),
+		client_id: "CI1".into(),
+		client_secret: Some("secret".into()),
+		redirect_port: Some(free_port()),
+		..Default::default()
+	}
+}
+
+fn free_port() -> u16 {
+	TcpListener::bind("127.0.0.1:0")
+		.unwrap()
+		.local_addr()
+		.unwrap()
+		.port()
+}
+
+fn param(url: &Url, name: &str) -> String {
+	url.query_pairs()
+		.find(|(k, _)| k == name)
+		.map(|(_, v)| v.into_owned())
+		.unwrap()
+}
+
+/// Plays the part of the browser: follows the authorize URL back to TIS as if the user
+/// clicked accept, or refused with `error`.
+fn browser(authorize: &str, error: Option<&'static str>) -> anyhow::Result<()> {
+	let authorize = Url::parse(authorize).unwrap();
+	assert_eq!(authorize.path(), "/oauth/request-auth");
+	assert_eq!(param(&authorize, "clientID"), "CI1");
+
+	let mut redirect = Url::parse(&param(&authorize, "redirect_uri")).unwrap();
+	let context = param(&authorize, "context");
+
+	tokio::spawn(async move {
+		// Things unrelated to the login first, which should be ignored.
+		let mut garbage =
+			tokio::net::TcpStream::connect(redirect.socket_addrs(|| None).unwrap()[0])
+				.await
+				.unwrap();
+		garbage.write_all(b"\xff\xfe\r\n\r\n").await.unwrap();
+		drop(garbage);
+
+		let mut favicon = redirect.clone();
+		favicon.set_path("/favicon.ico");
+		reqwest::get(favicon).await.unwrap();
+
+		match error {
+			Some(error) => redirect
+				.query_pairs_mut()
+				.append_pair("error", error)
+				.append_pair("context", &context),
+			None => redirect
+				.query_pairs_mut()
+				.append_pair("code", "the-code")
+				.append_pair("context", &context),
+		};
+		reqwest::get(redirect).await.unwrap();
+	});
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn logs_in_through_the_browser() {
+	let mock = MockServer::start();
+	mock.on(
+		"POST",
+		TOKEN,
+		vec![Response::json(
+			200,
+			json!({ "success": true, "description": "Token made.", "body": { "token": "new-token" } }),
+		)],
+	);
+
+	let token = oauth::login(
+		&server(&mock),
+		|url| browser(url, None),
+		Duration::from_secs(10),
+	)
+	.await
+	.unwrap();
+
+	assert_eq!(token, "new-token");
+
+	let exchange = mock.requests_to(TOKEN)[0].json();
+	assert_eq!(exchange["code"], "the-code");
+	assert_eq!(exchange["client_id"], "CI1");
+	assert_eq!(exchange["client_secret"], "secret");
+	assert_eq!(exchange["grant_type"], "authorization_code");
+	assert!(exchange["redirect_uri"]
+		.as_str()
+		.unwrap()
+		.starts_with("http://127.0.0.1:"));
+}
+
+#[tokio::test]
+async fn refusing_access_is_an_error() {
+	let mock = MockServer::start();
+
+	let err = oauth::login(
+		&server(&mock),
+		|url| browser(url, Some("access_denied")),
+		Duration::from_secs(10),
+	)
+	.await
+	.unwrap_err();
+
+	assert!(err.to_string().contains("access_denied"), "{err}");
+	assert!(mock.requests_to(TOKEN).is_empty());
+}
+
+#[tokio::test]
+async fn rejected_code_reports_description() {
+	let mock = MockServer::start();
+	mock.on(
+		"POST",
+		TOKEN,
+		vec![Response::json(
+			400,
+			json!({ "success": false, "description": "Invalid code." }),
+		)],
+	);
+
+	let err = oauth::login(
+		&server(&mock),
+		|url| browser(url, None),
+		Duration::from_secs(10),
+	)
+	.await
+	.unwrap_err();
+
+	assert!(err.to_string().contains("Invalid code."), "{err}");
+}
+
+#[tokio::test]
+async fn gives_up_if_nobody_logs_in() {
+	let mock = MockServer::start();
+
+	let err = oauth::login(&server(&mock), |_| Ok(()), Duration::from_millis(200))
+		.await
+		.unwrap_err();
+
+	assert!(err.to_string().contains("Gave up"), "{err}");
+}
+
+#[tokio::test]
+async fn needs_a_secret_and_port_before_opening_the_browser() {
+	let mock = MockServer::start();
+
+	for (config, missing) in [
+		(
+			ServerConfig {
+				client_secret: None,
+				..server(&mock)
+			},
+			"client_secret",
+		),
+		(
+			ServerConfig {
+				redirect_port: None,
+				..server(&mock)
+			},
+			"redirect_port",
+		),
+	] {
+		let err = oauth::login(
+			&config,
+			|_| panic!("opened the browser"),
+			Duration::from_secs(10),
+		)
+		.await
+		.unwrap_err();
+
+		assert!(err.to_string().contains(missing), "{err}");
+	}
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use std::{net::TcpListener, time::Duration};
+
+use import_scripts_lib::backend::{
+	client::{ClientOptions, TachiClient},
+	config::ServerConfig,
+	jobs::{JobHandle, JobKind},
+	queue::{self, QueueState},
+};
+use serde_json::json;
+
+use common::{
+	batch_manual, completed, import_accepted, in_temp_dir, me, status, MockServer, Response, ME,
+	STATUS,
+};
+
+const IMPORT: &str = "/ir/direct-manual/import";
+const POLL: &str = "/api/v1/imports/1/poll-status";
+
+fn client(base_url: &str) -> TachiClient {
+	let config = ServerConfig {
+		base_url: base_url.into(),
+		..Default::default()
+	};
+
+	TachiClient::new(
+		&config,
+		"token",
+		ClientOptions {
+			initial_backoff: Duration::from_millis(10),
+			max_retries: 1,
+			..Default::default()
+		},
+	)
+	.unwrap()
+}
+
+fn job() -> JobHandle {
+	JobHandle::detached(JobKind::Import)
+}
+
+fn state_of(id: u64) -> QueueState {
+	queue::list()
+		.into_iter()
+		.find(|e| e.id == id)
+		.expect("item went missing")
+		.state
+}
+
+// Every test here would share the queue file, so it's all one test.
+#[tokio::test]
+async fn queued_imports() {
+	in_temp_dir();
+
+	// Nothing is listening here once the listener is dropped.
+	let offline = {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		format!("http://{}", listener.local_addr().unwrap())
+	};
+
+	let mut first = batch_manual();
+	first["scores"] = json!([]);
+	let item = queue::enqueue(Some("beatoraja"), "Bokutachi", &first).unwrap();
+	assert_eq!(item.state, QueueState::Pending);
+
+	queue::upload(&client(&offline), item.id, true, &job())
+		.await
+		.unwrap_err();
+	assert_eq!(state_of(item.id), QueueState::Pending);
+
+	// Converting again while it's still pending merges into it, rather than queueing the same
+	// scores twice.
+	let again = queue::enqueue(Some("beatoraja"), "Bokutachi", &batch_manual()).unwrap();
+	assert_eq!(again.id, item.id);
+	assert_eq!(again.scores, 1);
+	assert_eq!(queue::list().len(), 1);
+
+	// Scores only the older one had aren't lost.
+	let mut older = batch_manual();
+	older["scores"][0]["timeAchieved"] = json!(1500000000000i64);
+	older["classes"] = json!({ "genocideDan": "INSANE_1" });
+	let again = queue::enqueue(Some("beatoraja"), "Bokutachi", &older).unwrap();
+	assert_eq!(again.id, item.id);
+	assert_eq!(again.scores, 2);
+
+	let again = queue::enqueue(Some("beatoraja"), "Bokutachi", &batch_manual()).unwrap();
+	assert_eq!(again.scores, 2);
+
+	// Uploads are in the order the scores were set.
+	let mut merged = older.clone();
+	merged["scores"]
+		.as_array_mut()
+		.unwrap()
+		.push(batch_manual()["scores"][0].clone());
+
+	// Nothing goes to a server the token can't import to.
+	let server = MockServer::start();
+	server
+		.on("GET", STATUS, vec![status(Some(1), &[])])
+		.on("GET", ME, vec![me()]);
+
+	queue::upload_pending(&client(server.base_url())).await;
+
+	assert_eq!(state_of(item.id), QueueState::Pending);
+	assert!(server.requests_to(IMPORT).is_empty());
+
+	let server = MockServer::start();
+	server
+		.on("GET", STATUS, vec![status(Some(1), &["submit_score"])])
+		.on("GET", ME, vec![me()])
+		.on("POST", IMPORT, vec![import_accepted(&server)])
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	queue::upload_pending(&client(server.base_url())).await;
+
+	assert_eq!(state_of(item.id), QueueState::Done);
+	assert_eq!(server.requests_to(IMPORT)[0].json(), merged);
+	assert_eq!(server.requests_to(IMPORT).len(), 1);
+
+	// A server turning it down is different.
+	let server = MockServer::start();
+	server
+		.on(
+			"POST",
+			IMPORT,
+			vec![
+				Response::json(
+					400,
+					json!({ "success": false, "description": "Invalid batch-manual." }),
+				),
+				import_accepted(&server),
+			],
+		)
+		.on("GET", POLL, vec![completed(&["a"], 0)]);
+
+	let client = client(server.base_url());
+	let done = item;
+	let item = queue::enqueue(None, "Bokutachi", &batch_manual()).unwrap();
+
+	// Finished imports are cleared out once something new is queued.
+	assert!(queue::list().iter().all(|e| e.id != done.id));
+	assert!(!done.path.exists());
+
+	queue::upload(&client, item.id, true, &job())
+		.await
+		.unwrap_err();
+
+	let failed = queue::list().into_iter().find(|e| e.id == item.id).unwrap();
+	assert_eq!(failed.state, QueueState::Failed);
+	assert!(failed
+		.error
+		.is_some_and(|e| e.contains("Invalid batch-manual.")));
+
+	// Failed imports are left alone until someone asks for them again.
+	queue::upload_pending(&client).await;
+	assert_eq!(server.requests_to(IMPORT).len(), 1);
+
+	let documents = queue::upload(&client, item.id, true, &job()).await.unwrap();
+	assert_eq!(documents[0].score_ids, ["a"]);
+	assert_eq!(state_of(item.id), QueueState::Done);
+}
@@ -0,0 +1,205 @@
+use import_scripts_lib::backend::validate::{validate_batch_manual, Severity};
+use serde_json::{json, Value};
+
+fn bms(score: Value) -> Value {
+	json!({
+		"meta": { "game": "bms", "playtype": "7K", "service": "TIS. v2.2.0" },
+		"scores": [score],
+		"classes": {},
+	})
+}
+
+fn usc(score: Value) -> Value {
+	json!({
+		"meta": { "game": "usc", "playtype": "Controller", "service": "TIS. v2.2.0" },
+		"scores": [score],
+		"classes": {},
+	})
+}
+
+fn messages(bm: &Value) -> Vec<String> {
+	validate_batch_manual(bm)
+		.into_iter()
+		.map(|e| e.to_string())
+		.collect()
+}
+
+fn severities(bm: &Value) -> Vec<Severity> {
+	validate_batch_manual(bm)
+		.into_iter()
+		.map(|e| e.severity)
+		.collect()
+}
+
+#[test]
+fn valid_scores_have_no_problems() {
+	assert!(messages(&bms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "bmsChartHash",
+		"score": 2502,
+		"lamp": "FULL COMBO",
+		"timeAchieved": 1600000000000i64,
+		"judgements": { "pgreat": 1200, "great": 102, "good": 0, "bad": 0, "poor": 0 },
+		"optional": { "bp": 0 },
+	})))
+	.is_empty());
+
+	assert!(messages(&usc(json!({
+		"identifier": "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+		"matchType": "uscChartHash",
+		"score": 10000000,
+		"lamp": "PERFECT ULTIMATE CHAIN",
+		"timeAchieved": null,
+		"judgements": { "critical": 1000, "near": 0, "miss": 0 },
+	})))
+	.is_empty());
+}
+
+#[test]
+fn identifiers_must_match_their_match_type() {
+	let problems = messages(&bms(json!({
+		"identifier": "not-a-hash",
+		"matchType": "bmsChartHash",
+		"score": 0,
+		"lamp": "FAILED",
+	})));
+	assert_eq!(problems.len(), 1);
+	assert!(
+		problems[0].starts_with("scores[0] (not-a-hash): bmsChartHash must be"),
+		"{problems:?}"
+	);
+
+	// An md5 is the wrong length for USC, which uses sha1.
+	let problems = messages(&usc(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "uscChartHash",
+		"score": 0,
+		"lamp": "FAILED",
+	})));
+	assert_eq!(problems.len(), 1);
+}
+
+#[test]
+fn lamps_must_agree_with_judgements() {
+	let bm = bms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "bmsChartHash",
+		"score": 100,
+		"lamp": "FULL COMBO",
+		"judgements": { "pgreat": 47, "great": 6, "good": 0, "bad": 3, "poor": 0 },
+	}));
+	let problems = messages(&bm);
+	assert!(
+		problems[0].contains("FULL COMBO, but there are 3 bads"),
+		"{problems:?}"
+	);
+	// The games don't keep these in step, so Tachi takes them anyway.
+	assert_eq!(severities(&bm), [Severity::Warning]);
+
+	let bm = bms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "bmsChartHash",
+		"score": 100,
+		"lamp": "FULL COMBO",
+		"optional": { "bp": 3 },
+	}));
+	let problems = messages(&bm);
+	assert!(
+		problems[0].contains("FULL COMBO, but bp is 3"),
+		"{problems:?}"
+	);
+	assert_eq!(severities(&bm), [Severity::Warning]);
+
+	let bm = usc(json!({
+		"identifier": "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+		"matchType": "uscChartHash",
+		"score": 9999999,
+		"lamp": "PERFECT ULTIMATE CHAIN",
+	}));
+	let problems = messages(&bm);
+	assert!(
+		problems[0].contains("PERFECT ULTIMATE CHAIN, but score is 9999999"),
+		"{problems:?}"
+	);
+	assert_eq!(severities(&bm), [Severity::Warning]);
+}
+
+#[test]
+fn out_of_range_numbers_are_problems() {
+	let problems = messages(&bms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "bmsChartHash",
+		"score": 10,
+		"lamp": "CLEAR",
+		"timeAchieved": 1600000000,
+		"judgements": { "pgreat": 5, "great": 0, "good": -1 },
+	})));
+	assert_eq!(problems.len(), 2, "{problems:?}");
+	assert!(problems
+		.iter()
+		.any(|e| e.contains("good can't be negative")));
+	assert!(problems.iter().any(|e| e.contains("looks like seconds")));
+
+	let bm = usc(json!({
+		"identifier": "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+		"matchType": "uscChartHash",
+		"score": 10000001,
+		"lamp": "CLEAR",
+	}));
+	let problems = messages(&bm);
+	assert!(problems[0].contains("more than 10000000"), "{problems:?}");
+	assert_eq!(severities(&bm), [Severity::Error]);
+}
+
+#[test]
+fn unreadable_scores_are_reported_by_index() {
+	let mut bm = bms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "bmsChartHash",
+		"score": 0,
+		"lamp": "FAILED",
+	}));
+	bm["scores"]
+		.as_array_mut()
+		.unwrap()
+		.push(json!({ "identifier": "x", "lamp": "ALL PERFECT" }));
+
+	let problems = messages(&bm);
+	assert_eq!(problems.len(), 1, "{problems:?}");
+	assert!(problems[0].starts_with("scores[1] (x): Invalid score"));
+}
+
+#[test]
+fn pms_scores_use_popn_judgements() {
+	let pms = |score: Value| {
+		json!({
+			"meta": { "game": "pms", "playtype": "Keyboard", "service": "TIS. v2.2.0" },
+			"scores": [score],
+			"classes": {},
+		})
+	};
+
+	assert!(messages(&pms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "pmsChartHash",
+		"score": 2502,
+		"lamp": "CLEAR",
+		"judgements": { "cool": 1200, "great": 102, "good": 0, "bad": 0, "poor": 0 },
+	})))
+	.is_empty());
+
+	let problems = messages(&pms(json!({
+		"identifier": "d41d8cd98f00b204e9800998ecf8427e",
+		"matchType": "bmsChartHash",
+		"score": 2500,
+		"lamp": "CLEAR",
+		"judgements": { "cool": 1200, "great": 102, "good": 0, "bad": 0, "poor": 0 },
+	})));
+	assert_eq!(
+		problems,
+		[
+			"scores[0] (d41d8cd98f00b204e9800998ecf8427e): Unsupported matchType 'bmsChartHash'.",
+			"(warning) scores[0] (d41d8cd98f00b204e9800998ecf8427e): score is 2500, but cool * 2 + great is 2502.",
+		]
+	);
+}
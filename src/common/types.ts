@@ -1,17 +1,35 @@
+export interface ServerConfig {
+	name: string;
+	base_url: string;
+	client_url: string;
+	client_id: string;
+	client_secret?: string | null;
+	redirect_port?: number | null;
+	auth_token?: string | null;
+}
+
 export interface TISConfig {
+	servers: ServerConfig[];
+	activeServer: string;
 	lr2DB?: {
 		scorePath?: string;
 		chartPath?: string;
+		pmsPlaytype?: "Controller" | "Keyboard" | null;
+		coursePath?: string | null;
 	};
 	beatorajaDB?: {
 		scorePath?: string;
 		chartPath?: string;
+		playHistory?: boolean;
+		scorelogBests?: boolean;
+		lnModes?: ("LN" | "CN" | "HCN")[];
+		pmsPlaytype?: "Controller" | "Keyboard" | null;
 	};
 	uscDB?: {
 		dbPath?: string;
 		playtype?: "Controller" | "Keyboard";
 	};
-	authToken: string | null;
 	warning: string;
-	staging?: boolean;
+	snapshotDBs?: boolean;
+	importTimeoutMinutes?: number;
 }
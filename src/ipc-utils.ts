@@ -1,50 +1,195 @@
 /* eslint-disable no-console */
-import { type BatchManual,type  ImportDocument,type  SuccessfulAPIResponse } from "tachi-common";
-import { type TISConfig } from "./common/types";
+import { type BatchManual } from "tachi-common";
+import { type ServerConfig, type TISConfig } from "./common/types";
 import { invoke } from "@tauri-apps/api/core";
 
 type Channels =
-	| "lr2_convert"
+	| "cancel_job"
+	| "check_auth"
+	| "convert"
+	| "detect_source"
+	| "export_report"
 	| "config"
 	| "import"
+	| "import_history"
+	| "list_queue"
 	| "log"
+	| "oauth_login"
+	| "remove_server"
+	| "retry_queue_item"
+	| "revert_import"
+	| "save_server"
+	| "set_active_server"
+	| "start_watch"
+	| "stop_watch"
 	| "update_api_token"
-	| "beatoraja_convert"
-	| "usc_convert";
+	| "update_snapshot_dbs";
 
-interface Reply {
-	lr2_convert: {
-		k7: BatchManual | null;
-		k14: BatchManual | null;
+export interface AuthStatus {
+	server: string;
+	userID: number;
+	username: string;
+	permissions: string[];
+	canSubmit: boolean;
+}
+
+export interface BMSConvertResults {
+	k7: BatchManual | null;
+	k14: BatchManual | null;
+	pms: BatchManual | null;
+}
+
+export type LNMode = "LN" | "CN" | "HCN";
+
+export type PMSPlaytype = "Controller" | "Keyboard";
+
+interface ConvertOpts {
+	lr2: {
+		scorePath: string;
+		chartPath: string;
+		pmsPlaytype: PMSPlaytype | null;
+		coursePath: string | null;
 	};
-	beatoraja_convert: {
-		k7: BatchManual | null;
-		k14: BatchManual | null;
+	beatoraja: {
+		scorePath: string;
+		chartPath: string;
+		playHistory: boolean;
+		scorelogBests: boolean;
+		lnModes: LNMode[];
+		pmsPlaytype: PMSPlaytype | null;
 	};
-	usc_convert: BatchManual;
+	usc: { dbPath: string; playtype: "Controller" | "Keyboard" };
+}
+
+export type Source = keyof ConvertOpts;
+
+export interface ConversionReport {
+	chartDb: string | null;
+	converted: number;
+	alreadyImported: number;
+	skipCounts: Record<string, number>;
+	skipped: { reason: string; identifier: string | null; title: string | null }[];
+	summary: string[];
+}
+
+export type JobEvent = { id: number; kind: "convert" | "import" } & (
+	| { status: "started" | "finished" | "cancelled" }
+	| { status: "converting"; rowsScanned: number; scoresKept: number }
+	| { status: "importing"; description: string }
+	| { status: "failed"; error: string }
+);
+
+/** The parts of Tachi's import document that the backend keeps. */
+export interface ImportDocument {
+	importID: string;
+	game: string;
+	playtypes: string[];
+	scoreIDs: string[];
+	errors: { type: string; message: string }[];
+	createdSessions: { sessionID: string; type: "Created" | "Appended" }[];
+	classDeltas: { set: string; playtype: string; old: string | null; new: string }[];
+	timeStarted: number | null;
+	timeFinished: number | null;
+	leftOut: Problem[];
+}
+
+export interface Problem {
+	index: number | null;
+	identifier: string | null;
+	message: string;
+	severity: "error" | "warning";
+}
+
+export interface ImportRecord {
+	importID: string;
+	url: string;
+	server: string;
+	time: number;
+	source: Source | null;
+	game: string;
+	playtype: string;
+	scoresSent: number;
+	newScores: number;
+	failed: number;
+	batchManual: string;
+	reverted: boolean;
+}
+
+export interface QueueItem {
+	id: number;
+	source: Source | null;
+	game: string;
+	playtype: string;
+	scores: number;
+	path: string;
+	added: number;
+	state: "pending" | "uploading" | "done" | "failed";
+	attempts: number;
+	error: string | null;
+}
+
+export interface Converted<T> {
+	output: T;
+	report: ConversionReport;
+}
+
+interface Reply {
+	cancel_job: boolean;
+	check_auth: AuthStatus;
+	convert: Converted<BMSConvertResults | BatchManual>;
+	detect_source: Source | null;
+	export_report: null;
 	config: TISConfig;
-	import: null | SuccessfulAPIResponse<ImportDocument>;
+	import: ImportDocument[];
+	import_history: ImportRecord[];
+	list_queue: QueueItem[];
+	oauth_login: string;
 	log: boolean;
+	remove_server: null;
+	retry_queue_item: ImportDocument[];
+	revert_import: null;
+	save_server: null;
+	set_active_server: null;
+	start_watch: null;
+	stop_watch: null;
 	update_api_token: boolean;
+	update_snapshot_dbs: null;
 }
 
 interface Content {
-	lr2_convert: {opts: {
-		scorePath: string;
-		chartPath: string;
-	}};
-	beatoraja_convert: {opts: {
-		scorePath: string;
-		chartPath: string;
-	}};
-	usc_convert: {opts: { dbPath: string; playtype: "Controller" | "Keyboard" } };
+	cancel_job: { id: number };
+	check_auth: { server?: string };
+	convert: {
+		[S in Source]: { source: S; opts: ConvertOpts[S]; fullResync: boolean; server?: string };
+	}[Source];
+	detect_source: { path: string };
+	export_report: { report: ConversionReport; path: string };
 	config: {};
-	import: { bm: BatchManual };
+	import: { source: Source; bm: BatchManual; server?: string };
+	import_history: {
+		query: {
+			source?: Source;
+			game?: string;
+			playtype?: string;
+			since?: number;
+			limit?: number;
+		};
+	};
+	list_queue: {};
 	log: {
 		level: "info" | "warn" | "error";
 		content: string;
 	};
+	oauth_login: { server?: string };
+	remove_server: { name: string };
+	retry_queue_item: { id: number };
+	revert_import: { importId: string; server?: string };
+	save_server: { server: ServerConfig };
+	set_active_server: { name: string };
+	start_watch: { settleMinutes: number };
+	stop_watch: {};
 	update_api_token: {token: string};
+	update_snapshot_dbs: { snapshot: boolean };
 }
 
 export function MakeIPCRequest<C extends Channels>(
@@ -1,3 +1,5 @@
+import type { ServerConfig, TISConfig } from "./common/types";
+
 const BokutachiConfig = {
 	name: "Bokutachi",
 	baseUrl: "https://boku.tachi.ac",
@@ -8,3 +10,8 @@ const BokutachiConfig = {
 export function GetTachiConfig() {
 	return BokutachiConfig;
 }
+
+/** The server imports go to, falling back to the first one like the backend does. */
+export function GetActiveServer(config: TISConfig): ServerConfig | undefined {
+	return config.servers.find((e) => e.name === config.activeServer) ?? config.servers[0];
+}